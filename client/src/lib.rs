@@ -1,9 +1,16 @@
 use crate::ClientStatus::Playing;
-use game::game::{GameObstacle, GamePlayer, GameState};
+use game::codec::{self, PlayerOrder};
+use game::game::{GameObstacle, GameState};
 use game::input::Input;
-use game::messages::{C2SMessage, LobbyState, S2CMessage};
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet, VecDeque};
+use game::messages::{
+    C2SMessage, LobbyConfig, LobbyState, PlayerInfo, PlayerState, S2CMessage, Standing,
+    TargetingStrategy, CHECKSUM_INTERVAL_TICKS,
+};
+use game::replay::{Replay, ReplayPlayer};
+use game::rollback::Prediction;
+use serde::Serialize;
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
 use uuid::Uuid;
 use wasm_bindgen::prelude::*;
 
@@ -20,104 +27,507 @@ macro_rules! console_log {
 #[wasm_bindgen(raw_module = "websocket")]
 extern "C" {
     pub fn sendMessage(s: &str);
+    #[wasm_bindgen(js_name = sendMessage)]
+    fn send_binary_message(bytes: &[u8]);
 }
 
+thread_local! {
+    static USE_BINARY: Cell<bool> = const { Cell::new(false) };
+}
+
+fn send_message(message: &C2SMessage) {
+    if USE_BINARY.with(Cell::get) {
+        send_binary_message(&codec::encode_binary(message));
+    } else {
+        sendMessage(serde_json::to_string(message).unwrap().as_str());
+    }
+}
+
+// Ticks the local player is fast-forwarded after resuming, so that its inputs reach the server
+// before the server simulates the tick they were made on
+const RESUME_LEAD_TICKS: u64 = 5;
+// Past states of the local game kept around to rewind to when the server disagrees with it
+const LOCAL_HISTORY_TICKS: usize = 200;
+// How far the local game may be from the tick the server asked for before it is adjusted
+const TICK_SYNC_TOLERANCE: u64 = 2;
+
 #[derive(Debug)]
 pub enum ClientStatus {
     Connected,
     Playing(Uuid, LobbyState),
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct RenderState {
-    pub additionalObstacles: VecDeque<GameObstacle>,
-    pub player: GamePlayer,
+    // Watching a replay from the point of view of the given player
+    Replaying(Uuid),
+    Spectating(Uuid, LobbyState),
 }
 
 #[wasm_bindgen]
 pub struct GameClient {
     status: ClientStatus,
     lobby_name: String,
+    session_token: Option<Uuid>,
+    // Sent with every join so that ratings follow the player, issued by the server on the
+    // first join
+    player_token: Option<Uuid>,
+    config: LobbyConfig,
+    host: Option<Uuid>,
+    countdown: Option<u64>,
+    // Last states confirmed by the server, except for the local game
     game_states: HashMap<Uuid, GameState>,
+    // Players of the current game, which compact tick events refer to by index
+    player_order: PlayerOrder,
+    // Opponents predicted up to the tick of the local game
+    predictions: HashMap<Uuid, Prediction>,
+    players: HashMap<Uuid, PlayerInfo>,
+    leaderboard: Vec<(Uuid, u64)>,
+    strategy: TargetingStrategy,
+    targets: HashMap<Uuid, Uuid>,
+    badges: HashMap<Uuid, u32>,
+    standings: Vec<Standing>,
+    replay_id: Option<Uuid>,
+    replay: Option<ReplayPlayer>,
+    // Checksums of the local game, keyed by the tick they were taken after
+    local_checksums: HashMap<u64, u64>,
+    // States of the local game before each tick, with the input applied on that tick
+    local_history: VecDeque<(GameState, Input)>,
+    resync_on_desync: bool,
+    awaiting_snapshot: bool,
+    // Ticks the local game still has to be sped up (positive) or slowed down (negative) by
+    tick_adjustment: i64,
+    rtt_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TargetingView<'a> {
+    strategy: TargetingStrategy,
+    target: Option<&'a Uuid>,
+    attackers: Vec<&'a Uuid>,
+    badges: &'a HashMap<Uuid, u32>,
+}
+
+#[derive(Serialize)]
+struct LobbyView<'a> {
+    state: &'a LobbyState,
+    players: usize,
+    max_players: u32,
+    ready_players: usize,
+    is_host: bool,
+    is_ready: bool,
+    countdown: Option<u64>,
+}
+
+// Tokens that do not parse are dropped, so that the server issues a new one
+fn parse_player_token(player_token: Option<String>) -> Option<Uuid> {
+    player_token.and_then(|token| Uuid::parse_str(&token).ok())
 }
 
 #[wasm_bindgen]
 impl GameClient {
     #[wasm_bindgen(constructor)]
-    pub fn new(username: &str, lobby_name: &str) -> GameClient {
-        sendMessage(
-            serde_json::to_string(&C2SMessage::LobbyJoinRequest {
-                lobby_id: lobby_name.to_string(),
-                name: username.to_string(),
-            })
-            .unwrap()
-            .as_str(),
-        );
+    pub fn new(username: &str, lobby_name: &str, player_token: Option<String>) -> GameClient {
+        let player_token = parse_player_token(player_token);
+        send_message(&C2SMessage::LobbyJoinRequest {
+            lobby_id: lobby_name.to_string(),
+            name: username.to_string(),
+            config: None,
+            player_token,
+        });
+        GameClient::with_lobby_name(lobby_name).with_player_token(player_token)
+    }
+
+    // Creates a lobby with the given settings, e.g. `{ is_private: true }`. If the lobby already
+    // exists, it is joined as is.
+    pub fn create(
+        username: &str,
+        lobby_name: &str,
+        config: JsValue,
+        player_token: Option<String>,
+    ) -> Result<GameClient, JsValue> {
+        let config = serde_wasm_bindgen::from_value(config)?;
+        let player_token = parse_player_token(player_token);
+        send_message(&C2SMessage::LobbyJoinRequest {
+            lobby_id: lobby_name.to_string(),
+            name: username.to_string(),
+            config: Some(config),
+            player_token,
+        });
+        Ok(GameClient::with_lobby_name(lobby_name).with_player_token(player_token))
+    }
+
+    // Joins a public lobby picked by the server instead of a named one
+    pub fn queue(username: &str, player_token: Option<String>) -> GameClient {
+        let player_token = parse_player_token(player_token);
+        send_message(&C2SMessage::QueueForMatch {
+            name: username.to_string(),
+            player_token,
+        });
+        GameClient::with_lobby_name("").with_player_token(player_token)
+    }
+
+    // To be stored by the caller and passed in when joining later on
+    pub fn player_token(&self) -> Option<String> {
+        self.player_token.map(|token| token.to_string())
+    }
+
+    pub fn spectate(username: &str, lobby_name: &str) -> GameClient {
+        send_message(&C2SMessage::SpectateRequest {
+            name: username.to_string(),
+            lobby_id: lobby_name.to_string(),
+        });
+        GameClient::with_lobby_name(lobby_name)
+    }
+
+    // Plays a recorded game back without connecting to a lobby
+    pub fn from_replay(bytes: &[u8]) -> Result<GameClient, JsValue> {
+        let replay = Replay::from_bytes(bytes).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let player = ReplayPlayer::new(replay);
+
+        let mut client = GameClient::with_lobby_name("");
+        client.config.game = player.header().config;
+        for (uuid, username) in &player.header().players {
+            client.players.insert(
+                *uuid,
+                PlayerInfo {
+                    username: username.clone(),
+                    id: *uuid,
+                    state: PlayerState::Playing,
+                    placement: None,
+                    is_ready: false,
+                },
+            );
+        }
+        client.game_states = player
+            .states()
+            .map(|(uuid, state)| (uuid, state.clone()))
+            .collect();
+
+        let first = player.header().players.first().map(|(uuid, _)| *uuid);
+        client.status = ClientStatus::Replaying(first.unwrap_or_default());
+        client.replay = Some(player);
+        Ok(client)
+    }
+
+    fn with_player_token(mut self, player_token: Option<Uuid>) -> GameClient {
+        self.player_token = player_token;
+        self
+    }
+
+    fn with_lobby_name(lobby_name: &str) -> GameClient {
         GameClient {
             status: ClientStatus::Connected,
             lobby_name: lobby_name.to_string(),
+            session_token: None,
+            player_token: None,
+            config: LobbyConfig::default(),
+            host: None,
+            countdown: None,
             game_states: HashMap::new(),
+            player_order: PlayerOrder::default(),
+            predictions: HashMap::new(),
+            players: HashMap::new(),
+            leaderboard: vec![],
+            strategy: TargetingStrategy::default(),
+            targets: HashMap::new(),
+            badges: HashMap::new(),
+            standings: vec![],
+            replay_id: None,
+            replay: None,
+            local_checksums: HashMap::new(),
+            local_history: VecDeque::new(),
+            resync_on_desync: true,
+            awaiting_snapshot: false,
+            tick_adjustment: 0,
+            rtt_ms: None,
         }
     }
 
     pub fn on_message(&mut self, s: &str) {
-        let parsed = serde_json::from_str::<S2CMessage>(s);
-        match parsed {
-            Ok(message) => match message {
-                S2CMessage::LobbyJoinSuccess { player_id, players } => {
-                    self.status = Playing(player_id, LobbyState::Waiting);
-                    console_log!("{:?}", &players);
-                    for player_info in players {
-                        self.game_states.insert(player_info.id, GameState::new());
+        match serde_json::from_str::<S2CMessage>(s) {
+            Ok(message) => self.handle_message(message),
+            Err(e) => {
+                console_log!("Failed to parse ({}): {}", e, s);
+            }
+        }
+    }
+
+    pub fn on_binary_message(&mut self, bytes: &[u8]) {
+        // The server only sends binary messages once binary was negotiated, so answer in kind
+        USE_BINARY.with(|use_binary| use_binary.set(true));
+        match codec::decode_binary::<S2CMessage>(bytes) {
+            Ok(message) => self.handle_message(message),
+            Err(e) => {
+                console_log!("Failed to decode binary message ({})", e);
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: S2CMessage) {
+        match message {
+            S2CMessage::LobbyJoinSuccess {
+                lobby_id,
+                player_id,
+                session_token,
+                player_token,
+                config,
+                host,
+                players,
+            } => {
+                self.status = Playing(player_id, LobbyState::Waiting);
+                self.lobby_name = lobby_id;
+                self.session_token = Some(session_token);
+                self.player_token = Some(player_token);
+                self.config = config;
+                self.host = host;
+                console_log!("Joined lobby '{}': {:?}", self.lobby_name, &players);
+                for player_info in players {
+                    self.game_states.insert(player_info.id, GameState::new());
+                    self.players.insert(player_info.id, player_info);
+                }
+            }
+            S2CMessage::ResumeSuccess {
+                player_id,
+                session_token,
+                lobby_state,
+                config,
+                host,
+                players,
+                game_states,
+                player_order,
+            } => {
+                console_log!("Resumed session in lobby '{}'", self.lobby_name);
+                self.player_order = PlayerOrder::new(player_order);
+                self.status = Playing(player_id, lobby_state);
+                self.session_token = Some(session_token);
+                self.config = config;
+                self.host = host;
+                self.players = players.into_iter().map(|info| (info.id, info)).collect();
+                self.game_states = game_states.into_iter().collect();
+                self.predictions.clear();
+                // Recorded before the disconnect, so none of it matches the resumed game
+                self.local_history.clear();
+                self.local_checksums.clear();
+                self.tick_adjustment = 0;
+
+                if let Some(state) = self.game_states.get_mut(&player_id) {
+                    for _ in 0..RESUME_LEAD_TICKS {
+                        state.tick(Input::None);
+                    }
+                }
+            }
+            S2CMessage::SpectateSuccess {
+                spectator_id,
+                lobby_state,
+                config,
+                players,
+                game_states,
+                player_order,
+            } => {
+                console_log!("Spectating lobby '{}'", self.lobby_name);
+                self.player_order = PlayerOrder::new(player_order);
+                self.status = ClientStatus::Spectating(spectator_id, lobby_state);
+                self.config = config;
+                self.players = players.into_iter().map(|info| (info.id, info)).collect();
+                self.game_states = game_states.into_iter().collect();
+                self.predictions.clear();
+            }
+            S2CMessage::LobbyJoinEvent { player } => {
+                self.game_states.insert(player.id, GameState::new());
+                self.players.insert(player.id, player);
+            }
+            S2CMessage::LobbyJoinFailureResponse { .. } => {}
+            S2CMessage::GameStartEvent {
+                seed,
+                config,
+                player_order,
+            } => {
+                for state in self.game_states.values_mut() {
+                    *state = GameState::with_config(seed, config);
+                }
+                self.config.game = config;
+                self.player_order = PlayerOrder::new(player_order);
+                self.predictions.clear();
+            }
+            message @ S2CMessage::CompactGameTickEvent { .. } => {
+                match codec::expand(message, &self.player_order) {
+                    Some(message) => self.handle_message(message),
+                    // The tick is lost, so catch up from the server's copies of the games
+                    None => {
+                        console_log!("Tick event refers to an unknown player");
+                        self.request_snapshot(vec![]);
                     }
                 }
-                S2CMessage::LobbyJoinEvent { player } => {
-                    self.game_states.insert(player.id, GameState::new());
-                }
-                S2CMessage::LobbyJoinFailureResponse { .. } => {}
-                S2CMessage::GameTickEvent { players, tick } => {
-                    let ignore_uuid = match self.status {
-                        Playing(uuid, _) => uuid,
-                        _ => Uuid::nil(),
-                    };
-                    let mut seen = HashSet::new();
-                    for (uuid, input) in players {
-                        if uuid == ignore_uuid {
-                            continue;
-                        }
-                        if let Some(state) = self.game_states.get_mut(&uuid) {
-                            if tick > state.tick {
-                                state.tick(input);
-                            }
-                        }
-
-                        seen.insert(uuid);
+            }
+            S2CMessage::GameTickEvent {
+                players,
+                tick,
+                checksums,
+            } => {
+                let ignore_uuid = match self.status {
+                    Playing(uuid, _) => uuid,
+                    _ => Uuid::nil(),
+                };
+                let inputs: HashMap<Uuid, Input> = players.into_iter().collect();
+                for (uuid, state) in &mut self.game_states {
+                    if *uuid == ignore_uuid || tick < state.tick {
+                        continue;
                     }
-                    for (uuid, state) in &mut self.game_states {
-                        if uuid == &ignore_uuid {
-                            continue;
-                        }
-
-                        if seen.contains(&uuid) {
-                            continue;
-                        }
-
-                        if tick > state.tick {
-                            state.tick(Input::None);
-                        }
+
+                    let input = inputs.get(uuid).copied().unwrap_or(Input::None);
+                    state.tick(input);
+                    self.predictions
+                        .entry(*uuid)
+                        .or_default()
+                        .confirm(state, input);
+                }
+
+                if !checksums.is_empty() {
+                    self.check_desyncs(tick, &checksums);
+                }
+            }
+            S2CMessage::TargetChangeEvent { player, target, .. } => match target {
+                Some(target) => {
+                    self.targets.insert(player, target);
+                }
+                None => {
+                    self.targets.remove(&player);
+                }
+            },
+            S2CMessage::BadgeEvent { player, badges } => {
+                self.badges.insert(player, badges);
+            }
+            S2CMessage::LobbyClosedEvent => {
+                // The lobby is gone, so there is nothing left to resume
+                console_log!("Lobby '{}' closed", self.lobby_name);
+                self.session_token = None;
+            }
+            S2CMessage::KickedEvent => {
+                console_log!("Kicked from lobby '{}'", self.lobby_name);
+                self.status = ClientStatus::Connected;
+                self.session_token = None;
+            }
+            S2CMessage::PlayerReadyEvent { player, is_ready } => {
+                if let Some(info) = self.players.get_mut(&player) {
+                    info.is_ready = is_ready;
+                }
+            }
+            S2CMessage::HostChangeEvent { host } => {
+                self.host = Some(host);
+            }
+            S2CMessage::LobbySettingsChangeEvent { config } => {
+                self.config = config;
+            }
+            S2CMessage::LobbyLeaveEvent { player } => {
+                if let Playing(_, LobbyState::Waiting | LobbyState::Countdown)
+                | ClientStatus::Spectating(_, LobbyState::Waiting | LobbyState::Countdown) =
+                    self.status
+                {
+                    self.game_states.remove(&player);
+                    self.players.remove(&player);
+                }
+            }
+            S2CMessage::PlayerEliminatedEvent {
+                player,
+                tick,
+                placement,
+                ..
+            } => {
+                if let Some(info) = self.players.get_mut(&player) {
+                    info.state = PlayerState::Spectating;
+                    info.placement = Some(placement);
+                }
+                // The server decides when the local player died, so take its copy of the game
+                // if ours does not agree
+                if let Playing(local, _) = self.status {
+                    let agrees = self
+                        .game_states
+                        .get(&player)
+                        .is_some_and(|state| state.is_game_over && state.tick == tick + 1);
+                    if local == player && !agrees {
+                        self.request_snapshot(vec![player]);
                     }
                 }
-                S2CMessage::InvalidMessage { error } => {
-                    console_log!("Invalid message: {}", error);
+                if let Some(state) = self.game_states.get_mut(&player) {
+                    state.is_game_over = true;
+                    if let Some(prediction) = self.predictions.get_mut(&player) {
+                        prediction.rollback(state);
+                    }
                 }
-                S2CMessage::LobbyStateChangeEvent { new_state } => {
-                    if let Playing(uuid, _old_state) = &self.status {
-                        self.status = Playing(*uuid, new_state);
+            }
+            S2CMessage::GameOverEvent {
+                standings,
+                replay_id,
+            } => {
+                self.standings = standings;
+                self.replay_id = replay_id;
+            }
+            S2CMessage::AttackEvent {
+                target,
+                tick,
+                obstacles,
+                ..
+            } => {
+                let is_local =
+                    matches!(self.status, Playing(local, LobbyState::InPlay) if local == target);
+                if is_local && self.game_states.contains_key(&target) {
+                    self.schedule_local_attack(target, tick, &obstacles);
+                } else if let Some(state) = self.game_states.get_mut(&target) {
+                    state.schedule_attack(tick, &obstacles);
+                    if let Some(prediction) = self.predictions.get_mut(&target) {
+                        prediction.rollback(state);
                     }
                 }
-            },
-            Err(e) => {
-                console_log!("Failed to parse ({}): {}", e, s);
+            }
+            S2CMessage::LeaderboardEvent { scores, .. } => {
+                self.leaderboard = scores;
+            }
+            S2CMessage::PingEvent { id } => {
+                send_message(&C2SMessage::Pong { id });
+            }
+            S2CMessage::TimeSyncEvent {
+                tick,
+                input_delay,
+                rtt_ms,
+            } => {
+                self.rtt_ms = Some(rtt_ms);
+                if let Playing(uuid, LobbyState::InPlay) = self.status {
+                    if let Some(state) = self.game_states.get(&uuid) {
+                        let target = tick + input_delay;
+                        self.tick_adjustment = if state.tick.abs_diff(target) > TICK_SYNC_TOLERANCE
+                        {
+                            target as i64 - state.tick as i64
+                        } else {
+                            0
+                        };
+                    }
+                }
+            }
+            S2CMessage::InputAckEvent { tick, accepted } => {
+                if !accepted {
+                    self.reject_input(tick);
+                }
+            }
+            S2CMessage::SnapshotEvent { game_states } => {
+                self.awaiting_snapshot = false;
+                for (uuid, state) in game_states {
+                    self.apply_snapshot(uuid, state);
+                }
+            }
+            S2CMessage::InvalidMessage { error } => {
+                console_log!("Invalid message: {}", error);
+            }
+            S2CMessage::CountdownEvent { seconds_remaining } => {
+                self.countdown = Some(seconds_remaining);
+            }
+            S2CMessage::LobbyStateChangeEvent { new_state } => {
+                if new_state != LobbyState::Countdown {
+                    self.countdown = None;
+                }
+                match &self.status {
+                    Playing(uuid, _old_state) => self.status = Playing(*uuid, new_state),
+                    ClientStatus::Spectating(uuid, _old_state) => {
+                        self.status = ClientStatus::Spectating(*uuid, new_state)
+                    }
+                    _ => {}
+                }
             }
         }
     }
@@ -125,30 +535,394 @@ impl GameClient {
     pub fn tick(&mut self, input: Input) {
         console_log!("status={:?}", self.status);
 
-        if let Playing(uuid, LobbyState::InPlay) = self.status {
-            let state = self.game_states.get_mut(&uuid).unwrap();
-            let current_tick = state.tick;
+        if let Some(replay) = &mut self.replay {
+            replay.step();
+            for (uuid, state) in replay.states() {
+                self.game_states.insert(uuid, state.clone());
+            }
+            return;
+        }
 
-            state.tick(input);
+        if let Playing(uuid, LobbyState::InPlay) = self.status {
+            // Drift towards the lead the server asked for one tick at a time. Ticks are only
+            // skipped when there is no input, so that no key presses are lost.
+            if self.tick_adjustment < 0 && matches!(input, Input::None) {
+                self.tick_adjustment += 1;
+            } else {
+                self.step_local(uuid, input);
+                if self.tick_adjustment > 0 {
+                    self.tick_adjustment -= 1;
+                    self.step_local(uuid, Input::None);
+                }
+            }
 
-            if !matches!(input, Input::None) {
-                sendMessage(
-                    serde_json::to_string(&C2SMessage::GameInput {
-                        tick: current_tick,
-                        input,
-                    })
-                    .unwrap()
-                    .as_str(),
-                );
+            // Opponents are shown at the same tick as the local game
+            let target_tick = self.game_states[&uuid].tick;
+            for (opponent, state) in &self.game_states {
+                if *opponent != uuid {
+                    self.predictions
+                        .entry(*opponent)
+                        .or_default()
+                        .predict_to(state, target_tick);
+                }
             }
         }
     }
 
+    fn step_local(&mut self, uuid: Uuid, input: Input) {
+        let state = self.game_states.get_mut(&uuid).unwrap();
+        if state.is_game_over {
+            return;
+        }
+        let current_tick = state.tick;
+
+        self.local_history.push_back((state.clone(), input));
+        if self.local_history.len() > LOCAL_HISTORY_TICKS {
+            self.local_history.pop_front();
+        }
+
+        state.tick(input);
+
+        if current_tick.is_multiple_of(CHECKSUM_INTERVAL_TICKS) {
+            self.local_checksums.insert(current_tick, state.checksum());
+        }
+
+        if !matches!(input, Input::None) {
+            send_message(&C2SMessage::GameInput {
+                tick: current_tick,
+                input,
+            });
+        }
+    }
+
+    // Re-binds a new connection to the player this client joined as, if any
+    pub fn resume(&self) {
+        if let Some(session_token) = self.session_token {
+            send_message(&C2SMessage::Resume {
+                lobby_id: self.lobby_name.clone(),
+                session_token,
+            });
+        }
+    }
+
+    // Whether desyncs are corrected with a snapshot from the server, or only reported
+    pub fn set_resync_on_desync(&mut self, resync_on_desync: bool) {
+        self.resync_on_desync = resync_on_desync;
+    }
+
     pub fn game_state(&self) -> Result<JsValue, JsValue> {
-        if let Playing(uuid, _) = self.status {
-            Ok(serde_wasm_bindgen::to_value(&(uuid, &self.game_states))?)
+        if let Some(uuid) = self.viewed_player() {
+            let states: HashMap<&Uuid, &GameState> = self
+                .game_states
+                .iter()
+                .map(|(uuid, state)| {
+                    let predicted = self.predictions.get(uuid).and_then(Prediction::latest);
+                    (uuid, predicted.unwrap_or(state))
+                })
+                .collect();
+            Ok(serde_wasm_bindgen::to_value(&(uuid, states))?)
         } else {
             Ok(JsValue::null())
         }
     }
+
+    pub fn set_targeting_strategy(&mut self, strategy: TargetingStrategy) {
+        self.strategy = strategy;
+        send_message(&C2SMessage::SetTargetingStrategy { strategy });
+    }
+
+    pub fn targeting(&self) -> Result<JsValue, JsValue> {
+        let uuid = match self.local_player() {
+            Some(uuid) => uuid,
+            None => return Ok(JsValue::null()),
+        };
+
+        let view = TargetingView {
+            strategy: self.strategy,
+            target: self.targets.get(&uuid),
+            attackers: self
+                .targets
+                .iter()
+                .filter(|(_, target)| **target == uuid)
+                .map(|(attacker, _)| attacker)
+                .collect(),
+            badges: &self.badges,
+        };
+        Ok(serde_wasm_bindgen::to_value(&view)?)
+    }
+
+    pub fn set_ready(&mut self, is_ready: bool) {
+        send_message(&C2SMessage::SetReady { is_ready });
+    }
+
+    pub fn start_game(&self) {
+        send_message(&C2SMessage::StartGame);
+    }
+
+    pub fn kick_player(&self, player: &str) -> Result<(), JsValue> {
+        let player = Uuid::parse_str(player).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        send_message(&C2SMessage::KickPlayer { player });
+        Ok(())
+    }
+
+    pub fn transfer_host(&self, player: &str) -> Result<(), JsValue> {
+        let player = Uuid::parse_str(player).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        send_message(&C2SMessage::TransferHost { player });
+        Ok(())
+    }
+
+    pub fn change_lobby_settings(&self, config: JsValue) -> Result<(), JsValue> {
+        let config = serde_wasm_bindgen::from_value(config)?;
+        send_message(&C2SMessage::ChangeLobbySettings { config });
+        Ok(())
+    }
+
+    pub fn lobby(&self) -> Result<JsValue, JsValue> {
+        let (uuid, state) = match &self.status {
+            Playing(uuid, state) | ClientStatus::Spectating(uuid, state) => (uuid, state),
+            _ => return Ok(JsValue::null()),
+        };
+
+        let view = LobbyView {
+            state,
+            players: self.players.len(),
+            max_players: self.config.max_players,
+            ready_players: self.players.values().filter(|info| info.is_ready).count(),
+            is_host: self.host.as_ref() == Some(uuid),
+            is_ready: self.players.get(uuid).is_some_and(|info| info.is_ready),
+            countdown: self.countdown,
+        };
+        Ok(serde_wasm_bindgen::to_value(&view)?)
+    }
+
+    pub fn placement(&self) -> Option<u32> {
+        self.local_player()
+            .and_then(|uuid| self.players.get(&uuid))
+            .and_then(|info| info.placement)
+    }
+
+    // How often `tick` should be called for the current lobby
+    pub fn tick_interval_ms(&self) -> f64 {
+        1000.0 / self.config.game.tick_rate.max(1) as f64
+    }
+
+    // Round trip time to the server, once it has been measured
+    pub fn rtt_ms(&self) -> Option<u64> {
+        self.rtt_ms
+    }
+
+    pub fn replay_id(&self) -> Option<String> {
+        self.replay_id.map(|uuid| uuid.to_string())
+    }
+
+    // Compares the server's checksums of the games after `tick` with the local copies
+    fn check_desyncs(&mut self, tick: u64, checksums: &[(Uuid, u64)]) {
+        let local = match self.status {
+            Playing(uuid, _) => Some(uuid),
+            _ => None,
+        };
+
+        let mut desynced = vec![];
+        for (player, expected) in checksums {
+            let actual = if Some(*player) == local {
+                self.local_checksums.get(&tick).copied()
+            } else {
+                self.game_states
+                    .get(player)
+                    .filter(|state| state.tick == tick + 1)
+                    .map(|state| state.checksum())
+            };
+
+            match actual {
+                Some(actual) if actual != *expected => {
+                    console_log!("Desync of {} at tick {}", player, tick);
+                    send_message(&C2SMessage::DesyncReport {
+                        tick,
+                        player: *player,
+                        expected: *expected,
+                        actual,
+                    });
+                    desynced.push(*player);
+                }
+                _ => {}
+            }
+        }
+        self.local_checksums
+            .retain(|checksum_tick, _| *checksum_tick > tick);
+
+        if self.resync_on_desync && !desynced.is_empty() {
+            self.request_snapshot(desynced);
+        }
+    }
+
+    fn request_snapshot(&mut self, players: Vec<Uuid>) {
+        if !self.awaiting_snapshot {
+            self.awaiting_snapshot = true;
+            send_message(&C2SMessage::SnapshotRequest { players });
+        }
+    }
+
+    // The server simulated `tick` without our input, so replay the local game without it
+    fn reject_input(&mut self, tick: u64) {
+        let local = match self.status {
+            Playing(uuid, _) => uuid,
+            _ => return,
+        };
+        console_log!("Input for tick {} was rejected", tick);
+
+        match self
+            .local_history
+            .iter_mut()
+            .find(|(state, _)| state.tick == tick)
+        {
+            Some((_, input)) => {
+                *input = Input::None;
+                self.rewind_local(local, tick);
+            }
+            // Too far back to rewind, so start over from the server's copy
+            None => self.request_snapshot(vec![local]),
+        }
+    }
+
+    // The local game runs ahead of the server and may already have passed the tick an attack
+    // lands on, in which case it is replayed with the attack
+    fn schedule_local_attack(&mut self, uuid: Uuid, tick: u64, obstacles: &[GameObstacle]) {
+        let state = self.game_states.get_mut(&uuid).unwrap();
+        if tick >= state.tick {
+            state.schedule_attack(tick, obstacles);
+            return;
+        }
+        console_log!("Attack for tick {} arrived late", tick);
+
+        match self
+            .local_history
+            .iter_mut()
+            .find(|(state, _)| state.tick == tick)
+        {
+            Some((past, _)) => {
+                past.schedule_attack(tick, obstacles);
+                self.rewind_local(uuid, tick);
+            }
+            None => {
+                state.schedule_attack(tick, obstacles);
+                self.request_snapshot(vec![uuid]);
+            }
+        }
+    }
+
+    // Re-simulates the local game from the recorded state at `tick`, which must be in the history
+    fn rewind_local(&mut self, uuid: Uuid, tick: u64) {
+        let start = self
+            .local_history
+            .iter()
+            .position(|(state, _)| state.tick == tick)
+            .unwrap();
+        let mut state = self.local_history[start].0.clone();
+        self.local_checksums
+            .retain(|checksum_tick, _| *checksum_tick < tick);
+
+        let mut end = self.local_history.len();
+        for (idx, (past, input)) in self.local_history.iter_mut().enumerate().skip(start) {
+            // Anything recorded after the game ended no longer happened
+            if state.is_game_over {
+                end = idx;
+                break;
+            }
+
+            *past = state.clone();
+            let past_tick = state.tick;
+            state.tick(*input);
+            if past_tick.is_multiple_of(CHECKSUM_INTERVAL_TICKS) {
+                self.local_checksums.insert(past_tick, state.checksum());
+            }
+        }
+        self.local_history.truncate(end);
+
+        self.game_states.insert(uuid, state);
+    }
+
+    fn apply_snapshot(&mut self, uuid: Uuid, state: GameState) {
+        // The local game runs ahead of the server, so inputs made since the snapshot are
+        // re-applied to catch back up
+        if let Playing(local, LobbyState::InPlay) = self.status {
+            if local == uuid {
+                let tick = state.tick;
+                match self
+                    .local_history
+                    .iter_mut()
+                    .find(|(past, _)| past.tick == tick)
+                {
+                    Some((past, _)) => {
+                        *past = state;
+                        self.rewind_local(uuid, tick);
+                        return;
+                    }
+                    None => {
+                        self.local_history.clear();
+                        self.local_checksums.clear();
+                    }
+                }
+            }
+        }
+        if let Some(prediction) = self.predictions.get_mut(&uuid) {
+            prediction.rollback(&state);
+        }
+        self.game_states.insert(uuid, state);
+    }
+
+    fn local_player(&self) -> Option<Uuid> {
+        match self.status {
+            Playing(uuid, _) | ClientStatus::Replaying(uuid) => Some(uuid),
+            ClientStatus::Spectating(..) | ClientStatus::Connected => None,
+        }
+    }
+
+    // Player shown in the main view: the local player while they are alive, otherwise whoever
+    // is still playing
+    fn viewed_player(&self) -> Option<Uuid> {
+        let local = self.local_player();
+        let is_alive = |uuid: &Uuid| {
+            self.players
+                .get(uuid)
+                .is_none_or(|info| info.state == PlayerState::Playing)
+        };
+
+        match local {
+            Some(uuid) if is_alive(&uuid) => Some(uuid),
+            _ => self
+                .players
+                .keys()
+                .filter(|uuid| is_alive(uuid))
+                .min()
+                .copied()
+                .or(local),
+        }
+    }
+
+    pub fn is_spectating(&self) -> bool {
+        matches!(self.status, ClientStatus::Spectating(..))
+            || self
+                .local_player()
+                .and_then(|uuid| self.players.get(&uuid))
+                .is_some_and(|info| info.state == PlayerState::Spectating)
+    }
+
+    pub fn standings(&self) -> Result<JsValue, JsValue> {
+        Ok(serde_wasm_bindgen::to_value(&self.standings)?)
+    }
+
+    pub fn leaderboard(&self) -> Result<JsValue, JsValue> {
+        let entries: Vec<(&str, u64)> = self
+            .leaderboard
+            .iter()
+            .map(|(uuid, score)| {
+                let name = self
+                    .players
+                    .get(uuid)
+                    .map_or("unknown", |info| info.username.as_str());
+                (name, *score)
+            })
+            .collect();
+        Ok(serde_wasm_bindgen::to_value(&entries)?)
+    }
 }
@@ -1,23 +1,70 @@
-import init, {GameClient, Input} from "../../client/pkg/client.js";
-import {sendMessage, setAllMessageHandler, ws} from "./websocket";
+import init, {GameClient, Input, TargetingStrategy} from "../../client/pkg/client.js";
+import {sendMessage, setAllMessageHandler, setBinaryMessageHandler, setOpenHandler, ws} from "./websocket";
 import {Animation, animations, sprites} from './sprites'
 
+const apiEndpoint = location.hostname == 'localhost' ? 'http://localhost:8080' : location.origin;
+
 let client: GameClient | undefined;
-init().then(() => {
-    client = new GameClient("test_user", "lobby");
-    setAllMessageHandler(client.on_message.bind(client));
+const clientReady = init().then(async (): Promise<GameClient> => {
+    const params = new URLSearchParams(location.search);
+    // ?replay=id plays back a finished game instead of joining one
+    const replayId = params.get('replay');
+    if (replayId !== null) {
+        const response = await fetch(`${apiEndpoint}/api/replays/${replayId}`);
+        client = GameClient.from_replay(new Uint8Array(await response.arrayBuffer()));
+        return client;
+    }
+
+    // Players pick their name with ?name= or when asked, and it is remembered for later visits
+    let username = params.get('name') ?? localStorage.getItem('username') ?? prompt('Pick a name');
+    if (username === null || username.trim() === '') {
+        username = `player-${Math.floor(Math.random() * 10000)}`;
+    }
+    localStorage.setItem('username', username);
+    // Ratings are kept under a token the server hands out on the first join
+    const playerToken = localStorage.getItem('playerToken') ?? undefined;
+
+    // Named lobbies are joined with ?lobby=name or watched with ?spectate=name, otherwise the
+    // server picks a public lobby. Adding &private creates the named lobby as a private one.
+    const lobbyName = params.get('lobby');
+    const spectateName = params.get('spectate');
+    if (spectateName !== null) {
+        client = GameClient.spectate(username, spectateName);
+    } else if (lobbyName !== null && params.has('private')) {
+        client = GameClient.create(username, lobbyName, {is_private: true}, playerToken);
+    } else if (lobbyName !== null) {
+        client = new GameClient(username, lobbyName, playerToken);
+    } else {
+        client = GameClient.queue(username, playerToken);
+    }
+    const rememberPlayerToken = () => {
+        const token = client.player_token();
+        if (token !== undefined) localStorage.setItem('playerToken', token);
+    };
+    setAllMessageHandler((message: string) => {
+        client.on_message(message);
+        rememberPlayerToken();
+    });
+    setBinaryMessageHandler((bytes: Uint8Array) => {
+        client.on_binary_message(bytes);
+        rememberPlayerToken();
+    });
+    setOpenHandler(client.resume.bind(client));
+    return client;
 });
 
 type GameState = {
     score: number,
     tick: number,
     is_game_over: boolean,
+    combo: number,
+    incoming_obstacles: unknown[],
     player: {
         y: number,
         is_ducked: boolean
     },
     obstacles: {
-        category: 'Bird' | 'Cactus',
+        category: 'Bird' | 'Cactus' | 'LargeCactus',
         position: {
             x: number,
             y: number
@@ -25,6 +72,40 @@ type GameState = {
     }[]
 };
 
+type LeaderboardEntry = [string, number];
+
+type Standing = {
+    player: string,
+    username: string,
+    placement: number,
+    score: number,
+    badges: number
+};
+
+type Lobby = {
+    state: 'Waiting' | 'Countdown' | 'InPlay' | 'Ended',
+    players: number,
+    max_players: number,
+    ready_players: number,
+    is_host: boolean,
+    is_ready: boolean,
+    countdown: number | null
+};
+
+type Targeting = {
+    strategy: 'Random' | 'Attackers' | 'KOs' | 'Badges',
+    target: string | null,
+    attackers: string[],
+    badges: Map<string, number>
+};
+
+const STRATEGY_KEYS = {
+    'Digit1': TargetingStrategy.Random,
+    'Digit2': TargetingStrategy.Attackers,
+    'Digit3': TargetingStrategy.KOs,
+    'Digit4': TargetingStrategy.Badges,
+};
+
 type GameStateReturn = [
     string,
     Map<string, GameState>
@@ -39,34 +120,49 @@ const setupCanvas = (drawFn: (dt: number, h: number, w: number, ctx: CanvasRende
     const ctx = canvas.getContext("2d");
 
 
-    let lastInput = Input.None;
+    // Every key press and release is sent on its own tick, so that e.g. releasing jump in the
+    // same tick as pressing it or releasing duck does not replace the other input
+    const pendingInputs: Input[] = [];
+    const queueInput = (e: KeyboardEvent, input: Input) => {
+        if (!e.repeat) pendingInputs.push(input);
+    };
     canvas.addEventListener('keydown', (e) => {
         if (e.code === 'Space' || e.code === 'ArrowUp') {
-            lastInput = Input.Jump;
+            queueInput(e, Input.Jump);
         } else if (e.code === 'ArrowDown') {
-            lastInput = Input.Duck;
+            queueInput(e, Input.Duck);
+        } else if (e.code in STRATEGY_KEYS && client) {
+            client.set_targeting_strategy(STRATEGY_KEYS[e.code]);
+        } else if (e.code === 'KeyR' && client) {
+            const lobby = client.lobby() as Lobby | null;
+            if (lobby !== null) client.set_ready(!lobby.is_ready);
+        } else if (e.code === 'Enter' && client) {
+            client.start_game();
         }
     });
     canvas.addEventListener('keyup', (e) => {
-        if ((e.code === 'Space' || e.code === 'ArrowUp') && lastInput == Input.Jump) {
-            lastInput = Input.None;
+        if (e.code === 'Space' || e.code === 'ArrowUp') {
+            queueInput(e, Input.JumpRelease);
         } else if (e.code === 'ArrowDown') {
-            lastInput = Input.Unduck;
+            queueInput(e, Input.Unduck);
         }
     });
 
-    let lastTick = 0;
-    setInterval(() => {
-        if (!client) {
-            return;
-        }
-        let timestamp = Date.now();
-
-        client.tick(lastInput);
-        lastTick = timestamp;
-
-        lastInput = Input.None;
-    }, 50)
+    // Scheduled against a fixed start time so that late timers do not add up
+    let nextTick = 0;
+    const tickLoop = (client: GameClient) => {
+        client.tick(pendingInputs.shift() ?? Input.None);
+
+        // Read every tick, as the lobby's tick rate is only known once it has been joined
+        const interval = client.tick_interval_ms();
+        // Give up on catching up after long pauses, e.g. while the tab was in the background
+        nextTick = Math.max(nextTick + interval, performance.now() - 5 * interval);
+        setTimeout(() => tickLoop(client), Math.max(0, nextTick - performance.now()));
+    };
+    clientReady.then((client) => {
+        nextTick = performance.now() + client.tick_interval_ms();
+        setTimeout(() => tickLoop(client), client.tick_interval_ms());
+    });
 
     let lastRender = 0;
     let totalTime = 0;
@@ -129,8 +225,9 @@ setupCanvas((dt, h, w, ctx, canvas, totalTime) => {
         ctx.drawImage(image, x, y - image.height);
     }
 
-    const renderAnimation = (animation: Animation, x, y, isGameOver = false) => {
-        const curFrameIdx = isGameOver ? 0 : Math.floor((totalTime / animation.frameTimeMs)) % animation.frames.length;
+    // Frames follow the game tick so that they line up with the pixel-mask collision mode
+    const renderAnimation = (animation: Animation, x, y, tick: number, isGameOver = false) => {
+        const curFrameIdx = isGameOver ? 0 : Math.floor((tick * client.tick_interval_ms() / animation.frameTimeMs)) % animation.frames.length;
         const curFrame = animation.frames[curFrameIdx];
         renderImage(curFrame, x, y);
     }
@@ -166,7 +263,21 @@ setupCanvas((dt, h, w, ctx, canvas, totalTime) => {
 
     drawText(`ws: ${ws.readyState === ws.OPEN ? "connected" : "disconnected"}\n` +
         `fps: ${fps}\n` +
-        `tick: ${localState.tick}`, w - 10, 10, {xalign: 'right'});
+        `tick: ${localState.tick}\n` +
+        `score: ${localState.score}`, w - 10, 10, {xalign: 'right'});
+
+    const targeting = client.targeting() as Targeting;
+    drawText(`targeting: ${targeting.strategy} (1-4 to change)\n` +
+        `badges: ${targeting.badges.get(uuid) ?? 0}\n` +
+        `attackers: ${targeting.attackers.length}`, 10, 10, {style: '16px monospace'});
+
+    const leaderboard = client.leaderboard() as LeaderboardEntry[];
+    if (leaderboard.length > 0) {
+        drawText("Leaderboard\n" + leaderboard
+            .slice(0, 10)
+            .map(([name, score], idx) => `${idx + 1}. ${name} ${score}`)
+            .join("\n"), 10, h - 300, {style: '16px monospace'});
+    }
 
 
     const renderGameArea = (render_state: GameState) => {
@@ -188,10 +299,10 @@ setupCanvas((dt, h, w, ctx, canvas, totalTime) => {
 
         if (localState.tick == 0) {
             renderImage(sprites.stand, DINO_X, realY);
-        } else if (is_ducked) {
-            renderAnimation(animations.duck, DINO_X, realY, render_state.is_game_over);
+        } else if (is_ducked && gameY == 0) {
+            renderAnimation(animations.duck, DINO_X, realY, render_state.tick, render_state.is_game_over);
         } else {
-            renderAnimation(animations.run, DINO_X, realY, render_state.is_game_over);
+            renderAnimation(animations.run, DINO_X, realY, render_state.tick, render_state.is_game_over);
         }
 
         for (let i = 0; i < 5; ++i) {
@@ -206,14 +317,29 @@ setupCanvas((dt, h, w, ctx, canvas, totalTime) => {
         for (let obstacle of render_state.obstacles) {
             if (obstacle.position.x > GAME_WIDTH) continue;
             if (obstacle.position.y > GAME_HEIGHT) continue;
+            const obstacleY = GAME_HEIGHT - obstacle.position.y;
             if (obstacle.category === 'Bird') {
-                renderAnimation(animations.bird, obstacle.position.x, obstacle.position.y, render_state.is_game_over);
+                renderAnimation(animations.bird, obstacle.position.x, obstacleY, render_state.tick, render_state.is_game_over);
             }
             if (obstacle.category === 'Cactus') {
-                renderImage(sprites.small_cactus1, obstacle.position.x, obstacle.position.y);
+                renderImage(sprites.small_cactus1, obstacle.position.x, obstacleY);
+            }
+            if (obstacle.category === 'LargeCactus') {
+                renderImage(sprites.large_cactus1, obstacle.position.x, obstacleY);
             }
         }
 
+        if (render_state.incoming_obstacles.length > 0) {
+            ctx.save();
+            ctx.fillStyle = 'red';
+            drawText(`incoming: ${render_state.incoming_obstacles.length}`, 5, 5, {style: '16px monospace'});
+            ctx.restore();
+        }
+
+        if (render_state.combo > 1) {
+            drawText(`combo x${render_state.combo}`, GAME_WIDTH - 5, 5, {xalign: 'right', style: '16px monospace'});
+        }
+
         if (render_state.is_game_over) {
             ctx.fillStyle = 'gray';
             ctx.globalAlpha = 0.3;
@@ -234,6 +360,11 @@ setupCanvas((dt, h, w, ctx, canvas, totalTime) => {
         ctx.scale(scale, scale);
         ctx.translate(100 + i * (GAME_WIDTH + 100), 100 + j * (GAME_HEIGHT + 100));
         renderGameArea(gameState);
+        if (targeting.target === id || targeting.attackers.includes(id)) {
+            ctx.lineWidth = 6;
+            ctx.strokeStyle = targeting.target === id ? 'red' : 'orange';
+            ctx.strokeRect(0, 0, GAME_WIDTH, GAME_HEIGHT);
+        }
         ctx.restore();
 
         if (scale * (100 + (i + 2) * (GAME_WIDTH + 100)) > w) {
@@ -250,4 +381,37 @@ setupCanvas((dt, h, w, ctx, canvas, totalTime) => {
     ctx.fillStyle = 'white';
     renderGameArea(localState);
     ctx.restore();
+
+    const lobby = client.lobby() as Lobby | null;
+    if (lobby !== null && (lobby.state === 'Waiting' || lobby.state === 'Countdown')) {
+        const status = lobby.countdown !== null ?
+            `Starting in ${lobby.countdown}` :
+            `Waiting for players (${lobby.players}/${lobby.max_players}, ${lobby.ready_players} ready)`;
+        drawText(status, w / 2, (h - GAME_HEIGHT) / 2 - 40, {xalign: 'center', style: '32px monospace'});
+        drawText(`${lobby.is_ready ? "Ready" : "Not ready"} (R to toggle)` +
+            (lobby.is_host ? " - host, Enter to start" : ""),
+            w / 2, (h + GAME_HEIGHT) / 2 + 40, {xalign: 'center', style: '18px monospace'});
+    }
+
+    const placement = client.placement();
+    if (placement !== undefined) {
+        drawText(`#${placement}`, w / 2, (h - GAME_HEIGHT) / 2 - 40, {xalign: 'center', style: '32px monospace'});
+    }
+
+    if (client.is_spectating()) {
+        drawText("Spectating", w / 2, (h - GAME_HEIGHT) / 2 - 80, {xalign: 'center', style: '24px monospace'});
+    }
+
+    const standings = client.standings() as Standing[];
+    if (standings.length > 0) {
+        drawText("Final standings\n" + standings
+            .slice(0, 10)
+            .map(s => `${s.placement}. ${s.username} ${s.score} (${s.badges} badges)`)
+            .join("\n"), w / 2, (h + GAME_HEIGHT) / 2 + 40, {xalign: 'center', style: '18px monospace'});
+    }
+
+    const replayId = client.replay_id();
+    if (replayId !== undefined) {
+        drawText(`Replay: ?replay=${replayId}`, w / 2, h - 30, {xalign: 'center', style: '14px monospace'});
+    }
 });
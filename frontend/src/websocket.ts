@@ -2,6 +2,11 @@ export let ws: WebSocket = undefined;
 let sendQueue = [];
 let messageHandlers = new Map();
 let allMessageHandler: CallableFunction = console.log;
+let binaryMessageHandler: CallableFunction = console.log;
+let openHandler: CallableFunction = () => {};
+const RECONNECT_DELAY_MS = 1000;
+// Most preferred first; the server replies in the format of the one it picks
+const PROTOCOLS = ['dino99.msgpack', 'dino99.json'];
 
 const websocketEndpoint = location.hostname == 'localhost' ?
     'ws://localhost:8080/ws/' :
@@ -10,15 +15,26 @@ const websocketEndpoint = location.hostname == 'localhost' ?
 reconnect();
 
 export function reconnect(): WebSocket {
-    ws = new WebSocket(websocketEndpoint);
+    ws = new WebSocket(websocketEndpoint, PROTOCOLS);
+    ws.binaryType = 'arraybuffer';
 
     ws.onopen = () => {
+        openHandler();
         for (let item of sendQueue) {
             ws.send(item);
         }
+        sendQueue = [];
+    }
+
+    ws.onclose = () => {
+        setTimeout(reconnect, RECONNECT_DELAY_MS);
     }
 
     ws.onmessage = (e) => {
+        if (e.data instanceof ArrayBuffer) {
+            binaryMessageHandler(new Uint8Array(e.data));
+            return;
+        }
         allMessageHandler(e.data);
         if (messageHandlers.has(e.data.type)) {
             messageHandlers.get(e.data.type)(JSON.parse(e.data));
@@ -28,8 +44,10 @@ export function reconnect(): WebSocket {
     return ws;
 }
 
-export function sendMessage(msg: string) {
+export function sendMessage(msg: string | Uint8Array) {
     if (ws.readyState !== ws.OPEN) {
+        // Binary messages from the client are views into wasm memory, which may be reused
+        if (msg instanceof Uint8Array) msg = msg.slice();
         sendQueue.push(msg);
         return;
     }
@@ -40,6 +58,15 @@ export function setMessageHandler(type: string, callback: (data: object) => void
     messageHandlers.set(type, callback);
 }
 
+// Called whenever a connection is (re-)established, before any queued messages are sent
+export function setOpenHandler(callback: () => void) {
+    openHandler = callback;
+}
+
+export function setBinaryMessageHandler(callback: (data: Uint8Array) => void) {
+    binaryMessageHandler = callback;
+}
+
 export function setAllMessageHandler(callback: (data: string) => void) {
     console.log(callback)
     allMessageHandler = callback;
@@ -0,0 +1,72 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+// Sprites that take part in collisions, as (constant name, file name)
+const SPRITES: [(&str, &str); 9] = [
+    ("STAND", "stand.png"),
+    ("RUN1", "run1.png"),
+    ("RUN2", "run2.png"),
+    ("DUCK1", "duck1.png"),
+    ("DUCK2", "duck2.png"),
+    ("BIRD1", "bird1.png"),
+    ("BIRD2", "bird2.png"),
+    ("SMALL_CACTUS1", "small_cactus1.png"),
+    ("LARGE_CACTUS1", "large_cactus1.png"),
+];
+
+// Rows are stored bottom-up as bitsets with bit 0 being the leftmost pixel, so a sprite can be
+// at most 64 pixels wide.
+fn generate_mask(name: &str, path: &PathBuf) -> String {
+    let decoder = png::Decoder::new(File::open(path).unwrap());
+    let mut reader = decoder.read_info().unwrap();
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).unwrap();
+
+    assert_eq!(info.color_type, png::ColorType::Rgba, "{:?}", path);
+    assert_eq!(info.bit_depth, png::BitDepth::Eight, "{:?}", path);
+    assert!(info.width <= 64, "{:?} is wider than 64px", path);
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let mut rows = vec![];
+    for y in (0..height).rev() {
+        let mut row = 0u64;
+        for x in 0..width {
+            let alpha = buf[y * info.line_size + x * 4 + 3];
+            if alpha > 127 {
+                row |= 1 << x;
+            }
+        }
+        rows.push(row);
+    }
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "pub const {}: SpriteMask = SpriteMask {{ width: {}, height: {}, rows: &[",
+        name, width, height
+    )
+    .unwrap();
+    for row in rows {
+        writeln!(out, "    {:#018x},", row).unwrap();
+    }
+    writeln!(out, "] }};").unwrap();
+    out
+}
+
+fn main() {
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let sprite_dir = manifest_dir.join("../frontend/static/sprites");
+    println!("cargo:rerun-if-changed=build.rs");
+
+    let mut out = String::new();
+    for (name, file) in SPRITES {
+        let path = sprite_dir.join(file);
+        println!("cargo:rerun-if-changed={}", path.display());
+        out.push_str(&generate_mask(name, &path));
+    }
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("sprite_masks.rs");
+    fs::write(out_path, out).unwrap();
+}
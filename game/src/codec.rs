@@ -0,0 +1,237 @@
+use crate::messages::S2CMessage;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Websocket subprotocols offered by clients. Servers reply in the format of the protocol they
+// picked, while messages from clients are decoded by their frame type.
+pub const JSON_PROTOCOL: &str = "dino99.json";
+pub const BINARY_PROTOCOL: &str = "dino99.msgpack";
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WireFormat {
+    // Text frames
+    #[default]
+    Json,
+    // Binary frames holding MessagePack, with players numbered in tick events
+    Binary,
+}
+
+impl WireFormat {
+    pub fn from_protocol(protocol: &str) -> Option<WireFormat> {
+        match protocol {
+            JSON_PROTOCOL => Some(WireFormat::Json),
+            BINARY_PROTOCOL => Some(WireFormat::Binary),
+            _ => None,
+        }
+    }
+
+    pub fn protocol(&self) -> &'static str {
+        match self {
+            WireFormat::Json => JSON_PROTOCOL,
+            WireFormat::Binary => BINARY_PROTOCOL,
+        }
+    }
+}
+
+pub fn encode_binary<T: Serialize>(message: &T) -> Vec<u8> {
+    rmp_serde::to_vec(message).unwrap()
+}
+
+pub fn decode_binary<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, rmp_serde::decode::Error> {
+    rmp_serde::from_slice(bytes)
+}
+
+// Players of a game in the order compact tick events number them. Built once per game, as
+// every tick event is looked up in it.
+#[derive(Clone, Debug, Default)]
+pub struct PlayerOrder {
+    uuids: Vec<Uuid>,
+    indices: HashMap<Uuid, u16>,
+}
+
+impl PlayerOrder {
+    pub fn new(uuids: Vec<Uuid>) -> PlayerOrder {
+        let indices = uuids
+            .iter()
+            .enumerate()
+            .map(|(index, uuid)| (*uuid, index as u16))
+            .collect();
+        PlayerOrder { uuids, indices }
+    }
+
+    pub fn index(&self, uuid: &Uuid) -> Option<u16> {
+        self.indices.get(uuid).copied()
+    }
+
+    pub fn uuid(&self, index: u16) -> Option<Uuid> {
+        self.uuids.get(index as usize).copied()
+    }
+}
+
+// Refers to players in tick events by their index in `player_order` instead of their uuid
+pub fn compact(message: S2CMessage, player_order: &PlayerOrder) -> S2CMessage {
+    match message {
+        S2CMessage::GameTickEvent {
+            tick,
+            players,
+            checksums,
+        } => {
+            let indexed_players = players
+                .iter()
+                .map(|(uuid, input)| Some((player_order.index(uuid)?, *input)))
+                .collect::<Option<Vec<_>>>();
+            let indexed_checksums = checksums
+                .iter()
+                .map(|(uuid, checksum)| Some((player_order.index(uuid)?, *checksum)))
+                .collect::<Option<Vec<_>>>();
+
+            match (indexed_players, indexed_checksums) {
+                (Some(players), Some(checksums)) => S2CMessage::CompactGameTickEvent {
+                    tick,
+                    players,
+                    checksums,
+                },
+                // Players outside the order are sent as is
+                _ => S2CMessage::GameTickEvent {
+                    tick,
+                    players,
+                    checksums,
+                },
+            }
+        }
+        message => message,
+    }
+}
+
+// Reverses `compact`, failing if the event refers to a player outside `player_order`
+pub fn expand(message: S2CMessage, player_order: &PlayerOrder) -> Option<S2CMessage> {
+    match message {
+        S2CMessage::CompactGameTickEvent {
+            tick,
+            players,
+            checksums,
+        } => Some(S2CMessage::GameTickEvent {
+            tick,
+            players: players
+                .into_iter()
+                .map(|(index, input)| Some((player_order.uuid(index)?, input)))
+                .collect::<Option<_>>()?,
+            checksums: checksums
+                .into_iter()
+                .map(|(index, checksum)| Some((player_order.uuid(index)?, checksum)))
+                .collect::<Option<_>>()?,
+        }),
+        message => Some(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Input;
+    use crate::messages::C2SMessage;
+
+    fn order() -> PlayerOrder {
+        PlayerOrder::new((1..=3).map(Uuid::from_u128).collect())
+    }
+
+    fn tick_event(players: &[u128], checksums: &[u128]) -> S2CMessage {
+        S2CMessage::GameTickEvent {
+            tick: 7,
+            players: players
+                .iter()
+                .map(|id| (Uuid::from_u128(*id), Input::Jump))
+                .collect(),
+            checksums: checksums
+                .iter()
+                .map(|id| (Uuid::from_u128(*id), *id as u64 * 100))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn protocols() {
+        for format in [WireFormat::Json, WireFormat::Binary] {
+            assert_eq!(WireFormat::from_protocol(format.protocol()), Some(format));
+        }
+        assert_eq!(WireFormat::from_protocol("dino99.xml"), None);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let message = C2SMessage::GameInput {
+            input: Input::Duck,
+            tick: 12,
+        };
+        match decode_binary::<C2SMessage>(&encode_binary(&message)).unwrap() {
+            C2SMessage::GameInput { input, tick } => {
+                assert_eq!(input, Input::Duck);
+                assert_eq!(tick, 12);
+            }
+            message => panic!("decoded {:?}", message),
+        }
+
+        let message = compact(tick_event(&[2], &[1, 3]), &order());
+        let bytes = encode_binary(&message);
+        assert_eq!(
+            encode_binary(&decode_binary::<S2CMessage>(&bytes).unwrap()),
+            bytes
+        );
+        assert!(decode_binary::<S2CMessage>(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn compact_round_trip() {
+        let order = order();
+        match compact(tick_event(&[3, 1], &[1, 2, 3]), &order) {
+            S2CMessage::CompactGameTickEvent {
+                tick,
+                ref players,
+                ref checksums,
+            } => {
+                assert_eq!(tick, 7);
+                assert_eq!(players, &[(2, Input::Jump), (0, Input::Jump)]);
+                assert_eq!(checksums, &[(0, 100), (1, 200), (2, 300)]);
+            }
+            message => panic!("compacted to {:?}", message),
+        }
+
+        let event = tick_event(&[3, 1], &[1, 2, 3]);
+        let expanded = expand(compact(event.clone(), &order), &order).unwrap();
+        assert_eq!(encode_binary(&expanded), encode_binary(&event));
+    }
+
+    #[test]
+    fn unknown_players() {
+        let order = order();
+
+        // Players outside the order are left as uuids
+        let event = tick_event(&[4], &[]);
+        let compacted = compact(event.clone(), &order);
+        assert!(matches!(compacted, S2CMessage::GameTickEvent { .. }));
+        assert_eq!(encode_binary(&compacted), encode_binary(&event));
+
+        let event = S2CMessage::CompactGameTickEvent {
+            tick: 7,
+            players: vec![(0, Input::None)],
+            checksums: vec![(3, 100)],
+        };
+        assert!(expand(event, &order).is_none());
+        assert!(expand(
+            compact(tick_event(&[1], &[]), &order),
+            &PlayerOrder::default()
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn other_messages_pass_through() {
+        let order = order();
+        let message = compact(S2CMessage::PingEvent { id: 5 }, &order);
+        assert!(matches!(message, S2CMessage::PingEvent { id: 5 }));
+        let message = expand(message, &order).unwrap();
+        assert!(matches!(message, S2CMessage::PingEvent { id: 5 }));
+    }
+}
@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::min;
+
+// Upper bounds for difficulty settings, keeping obstacle gaps well within an i32
+pub const MAX_SPEED: u32 = 100;
+pub const MAX_GAP: u32 = 10_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DifficultyConfig {
+    pub initial_speed: u32,
+    pub max_speed: u32,
+    // Speed increases by one every `speed_step_ticks` ticks and every `speed_step_score` points.
+    // A step of 0 disables that part of the curve.
+    pub speed_step_ticks: u64,
+    pub speed_step_score: u64,
+    // Minimum distance between obstacles is `base_gap + gap_per_speed * speed`, with up to
+    // `gap_variance` extra distance picked at random.
+    pub base_gap: u32,
+    pub gap_per_speed: u32,
+    pub gap_variance: u32,
+}
+
+impl Default for DifficultyConfig {
+    fn default() -> Self {
+        DifficultyConfig::normal()
+    }
+}
+
+impl DifficultyConfig {
+    pub fn easy() -> DifficultyConfig {
+        DifficultyConfig {
+            initial_speed: 4,
+            max_speed: 10,
+            speed_step_ticks: 400,
+            speed_step_score: 0,
+            base_gap: 200,
+            gap_per_speed: 30,
+            gap_variance: 300,
+        }
+    }
+
+    pub fn normal() -> DifficultyConfig {
+        DifficultyConfig {
+            initial_speed: 5,
+            max_speed: 13,
+            speed_step_ticks: 200,
+            speed_step_score: 0,
+            base_gap: 150,
+            gap_per_speed: 30,
+            gap_variance: 250,
+        }
+    }
+
+    pub fn hard() -> DifficultyConfig {
+        DifficultyConfig {
+            initial_speed: 7,
+            max_speed: 18,
+            speed_step_ticks: 120,
+            speed_step_score: 0,
+            base_gap: 120,
+            gap_per_speed: 25,
+            gap_variance: 200,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.initial_speed == 0 || self.initial_speed > MAX_SPEED || self.max_speed > MAX_SPEED {
+            return Err("Invalid speeds!".to_string());
+        }
+        if self.base_gap > MAX_GAP || self.gap_per_speed > MAX_GAP || self.gap_variance > MAX_GAP {
+            return Err("Invalid obstacle gaps!".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn speed_at(&self, tick: u64, score: u64) -> u32 {
+        let steps = tick.checked_div(self.speed_step_ticks).unwrap_or(0)
+            + score.checked_div(self.speed_step_score).unwrap_or(0);
+
+        let speed = self.initial_speed as u64 + steps;
+        min(speed, self.max_speed.max(self.initial_speed) as u64) as u32
+    }
+
+    pub fn min_gap(&self, speed: u32) -> u32 {
+        self.base_gap + self.gap_per_speed * speed
+    }
+
+    pub fn max_gap(&self, speed: u32) -> u32 {
+        self.min_gap(speed) + self.gap_variance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(speed_step_ticks: u64, speed_step_score: u64) -> DifficultyConfig {
+        DifficultyConfig {
+            initial_speed: 5,
+            max_speed: 10,
+            speed_step_ticks,
+            speed_step_score,
+            base_gap: 100,
+            gap_per_speed: 20,
+            gap_variance: 50,
+        }
+    }
+
+    #[test]
+    fn speed_steps() {
+        let difficulty = curve(100, 1000);
+        assert_eq!(difficulty.speed_at(0, 0), 5);
+        assert_eq!(difficulty.speed_at(99, 999), 5);
+        assert_eq!(difficulty.speed_at(100, 0), 6);
+        assert_eq!(difficulty.speed_at(0, 1000), 6);
+        assert_eq!(difficulty.speed_at(250, 2500), 9);
+    }
+
+    #[test]
+    fn zero_step_disables_curve() {
+        assert_eq!(curve(0, 0).speed_at(1_000_000, 1_000_000), 5);
+        assert_eq!(curve(0, 1000).speed_at(1_000_000, 2000), 7);
+        assert_eq!(curve(100, 0).speed_at(200, 1_000_000), 7);
+    }
+
+    #[test]
+    fn speed_is_capped() {
+        assert_eq!(curve(1, 1).speed_at(1_000_000, 1_000_000), 10);
+
+        // A cap below the initial speed keeps the initial speed
+        let mut difficulty = curve(1, 0);
+        difficulty.max_speed = 2;
+        assert_eq!(difficulty.speed_at(0, 0), 5);
+        assert_eq!(difficulty.speed_at(1000, 0), 5);
+    }
+
+    #[test]
+    fn gaps_grow_with_speed() {
+        let difficulty = curve(100, 0);
+        assert_eq!(difficulty.min_gap(5), 200);
+        assert_eq!(difficulty.max_gap(5), 250);
+        assert_eq!(difficulty.min_gap(10), 300);
+    }
+}
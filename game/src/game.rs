@@ -1,16 +1,55 @@
+use crate::difficulty::DifficultyConfig;
+use crate::hitbox::{obstacle_hitboxes, player_hitboxes, Hitbox};
 use crate::input::Input;
+use crate::mask::{masks_overlap, obstacle_mask, player_mask, SpriteMask};
+use crate::rng::Rng;
 use serde::{Deserialize, Serialize};
-use std::cmp::min;
 use std::collections::VecDeque;
 #[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
+const PLAYER_X: i32 = 50;
+const SPAWN_X: i32 = 600;
+const BIRD_CHANCE_PERCENT: u32 = 30;
+const LARGE_CACTUS_CHANCE_PERCENT: u32 = 30;
+const BIRD_HEIGHTS: [i32; 3] = [10, 35, 60];
+
+const DISTANCE_POINTS: u64 = 1;
+const CLEARED_POINTS: u64 = 10;
+const NEAR_MISS_POINTS: u64 = 25;
+const NEAR_MISS_DISTANCE: i32 = 8;
+const SPEED_PER_MULTIPLIER: u32 = 3;
+
+// Clearing obstacles in quick succession builds a combo; every COMBO_STEP clears in a combo
+// and every near miss earn attack points that the server turns into obstacles for opponents
+const COMBO_WINDOW_TICKS: u64 = 100;
+const COMBO_STEP: u32 = 3;
+const NEAR_MISS_ATTACK_POINTS: u32 = 1;
+
+// Player physics use fixed-point numbers with FP_SHIFT fractional bits so that the server and
+// the wasm client always agree on positions
+const FP_SHIFT: u32 = 8;
+const FP_ONE: i32 = 1 << FP_SHIFT;
+const JUMP_VELOCITY: i32 = 13 * FP_ONE;
+const GRAVITY: i32 = 3 * FP_ONE;
+const HELD_GRAVITY: i32 = 5 * FP_ONE / 4;
+const MAX_JUMP_HOLD_TICKS: u8 = 8;
+const FAST_FALL_VELOCITY: i32 = 6 * FP_ONE;
+const FAST_FALL_GRAVITY: i32 = 6 * FP_ONE;
+
+// Physics steps per second. Everything GameState measures in ticks is tuned for this rate, so
+// lobbies running at a lower tick rate take several steps every tick.
+pub const PHYSICS_TICK_RATE: u32 = 20;
+
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GamePlayer {
     pub y: i32,
-    pub jump_tick: u8,
-    pub peak_jump_tick: u8,
+    pub y_fp: i32,
+    pub velocity_fp: i32,
+    pub jump_hold_ticks: u8,
+    pub is_jump_held: bool,
+    pub is_fast_falling: bool,
     pub is_ducked: bool,
     pub speed: u32,
 }
@@ -19,9 +58,18 @@ pub struct GamePlayer {
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum GameObstacleCategory {
     Cactus,
+    LargeCactus,
     Bird,
 }
 
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlayerPose {
+    Standing,
+    Ducking,
+    Jumping,
+}
+
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct BoundingBox {
@@ -29,55 +77,139 @@ pub struct BoundingBox {
     pub h: u32,
 }
 
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CollisionMode {
+    #[default]
+    Hitbox,
+    PixelMask,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    pub difficulty: DifficultyConfig,
+    pub collision: CollisionMode,
+    // Ticks per second. Has to divide PHYSICS_TICK_RATE.
+    pub tick_rate: u32,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        GameConfig {
+            difficulty: DifficultyConfig::default(),
+            collision: CollisionMode::default(),
+            tick_rate: PHYSICS_TICK_RATE,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tick_rate == 0 || !PHYSICS_TICK_RATE.is_multiple_of(self.tick_rate) {
+            return Err("Invalid tick rate!".to_string());
+        }
+        self.difficulty.validate()
+    }
+
+    pub fn steps_per_tick(&self) -> u64 {
+        (PHYSICS_TICK_RATE / self.tick_rate.max(1)) as u64
+    }
+}
+
 trait Collidable {
-    fn collision_box(&self) -> BoundingBox;
+    fn hitboxes(&self) -> &'static [Hitbox];
+    fn mask(&self, tick: u64) -> &'static SpriteMask;
     fn position(&self) -> Position;
+
+    fn bounding_box(&self) -> BoundingBox {
+        let hitboxes = self.hitboxes();
+        BoundingBox {
+            w: hitboxes.iter().map(|b| b.x + b.w as i32).max().unwrap_or(0) as u32,
+            h: hitboxes.iter().map(|b| b.y + b.h as i32).max().unwrap_or(0) as u32,
+        }
+    }
 }
 
-fn is_colliding(a: &dyn Collidable, b: &dyn Collidable) -> bool {
-    let a_collision = a.collision_box();
-    let a_position = a.position();
+fn bounds(a: &dyn Collidable) -> (i32, i32, i32, i32) {
+    let collision = a.bounding_box();
+    let position = a.position();
 
-    let (ax0, ax1, ay0, ay1) = (
-        a_position.x,
-        a_position.x + a_collision.w as i32,
-        a_position.y,
-        a_position.y + a_collision.h as i32,
-    );
+    (
+        position.x,
+        position.x + collision.w as i32,
+        position.y,
+        position.y + collision.h as i32,
+    )
+}
 
-    let b_collision = b.collision_box();
-    let b_position = b.position();
+fn world_hitboxes(a: &dyn Collidable) -> impl Iterator<Item = (i32, i32, i32, i32)> {
+    let position = a.position();
+    a.hitboxes().iter().map(move |b| {
+        let (x, y) = (position.x + b.x, position.y + b.y);
+        (x, x + b.w as i32, y, y + b.h as i32)
+    })
+}
 
-    let (bx0, bx1, by0, by1) = (
-        b_position.x,
-        b_position.x + b_collision.w as i32,
-        b_position.y,
-        b_position.y + b_collision.h as i32,
-    );
+fn is_colliding(a: &dyn Collidable, b: &dyn Collidable, mode: CollisionMode, tick: u64) -> bool {
+    match mode {
+        CollisionMode::Hitbox => world_hitboxes(a).any(|(ax0, ax1, ay0, ay1)| {
+            world_hitboxes(b)
+                .any(|(bx0, bx1, by0, by1)| ax0 < bx1 && ax1 > bx0 && ay0 < by1 && ay1 > by0)
+        }),
+        CollisionMode::PixelMask => {
+            let (a_position, b_position) = (a.position(), b.position());
+            masks_overlap(
+                a.mask(tick),
+                a_position.x,
+                a_position.y,
+                b.mask(tick),
+                b_position.x,
+                b_position.y,
+            )
+        }
+    }
+}
 
-    return ax0 <= bx1 && ax1 >= bx0 && ay0 <= by1 && ay1 >= by0;
+// Smallest vertical distance between any pair of hitboxes that overlap horizontally
+fn vertical_gap(a: &dyn Collidable, b: &dyn Collidable) -> Option<i32> {
+    world_hitboxes(a)
+        .flat_map(|(ax0, ax1, ay0, ay1)| {
+            world_hitboxes(b)
+                .filter(move |(bx0, bx1, _, _)| ax0 < *bx1 && ax1 > *bx0)
+                .map(move |(_, _, by0, by1)| (by0 - ay1).max(ay0 - by1))
+        })
+        .min()
 }
 
 impl Collidable for GameObstacle {
-    fn collision_box(&self) -> BoundingBox {
-        match self.category {
-            GameObstacleCategory::Cactus => BoundingBox { w: 32, h: 32 },
-            GameObstacleCategory::Bird => BoundingBox { w: 46, h: 30 },
-        }
+    fn hitboxes(&self) -> &'static [Hitbox] {
+        obstacle_hitboxes(self.category)
+    }
+
+    fn mask(&self, tick: u64) -> &'static SpriteMask {
+        obstacle_mask(self.category, tick)
     }
 
     fn position(&self) -> Position {
-        return self.position;
+        self.position
     }
 }
 
 impl Collidable for GamePlayer {
-    fn collision_box(&self) -> BoundingBox {
-        return BoundingBox { w: 45, h: 48 };
+    fn hitboxes(&self) -> &'static [Hitbox] {
+        player_hitboxes(self.pose())
+    }
+
+    fn mask(&self, tick: u64) -> &'static SpriteMask {
+        player_mask(self.pose(), tick)
     }
 
     fn position(&self) -> Position {
-        return Position { x: 50, y: self.y };
+        Position {
+            x: PLAYER_X,
+            y: self.y,
+        }
     }
 }
 
@@ -88,36 +220,95 @@ pub struct Position {
     pub y: i32,
 }
 
+// Obstacle positions are measured from the ground up, matching GamePlayer::y
 #[cfg_attr(feature = "wasm", wasm_bindgen)]
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GameObstacle {
     pub category: GameObstacleCategory,
     pub position: Position,
+    #[serde(default)]
+    pub cleared: bool,
+    #[serde(default)]
+    pub near_miss: bool,
+}
+
+impl GameObstacle {
+    pub fn random(rng: &mut Rng) -> GameObstacle {
+        let (category, y) = if rng.chance(BIRD_CHANCE_PERCENT) {
+            let height = BIRD_HEIGHTS[rng.range(0, BIRD_HEIGHTS.len() as u32) as usize];
+            (GameObstacleCategory::Bird, height)
+        } else if rng.chance(LARGE_CACTUS_CHANCE_PERCENT) {
+            (GameObstacleCategory::LargeCactus, 0)
+        } else {
+            (GameObstacleCategory::Cactus, 0)
+        };
+
+        GameObstacle {
+            category,
+            position: Position { x: SPAWN_X, y },
+            cleared: false,
+            near_miss: false,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct GameState {
     pub score: u64,
     pub player: GamePlayer,
     pub obstacles: VecDeque<GameObstacle>,
     pub tick: u64,
     pub is_game_over: bool,
+    pub config: GameConfig,
+    pub combo: u32,
+    pub attack_points: u32,
+    pub incoming_obstacles: VecDeque<(u64, GameObstacle)>,
+    // In physics steps rather than ticks
+    last_clear_step: u64,
+    rng: Rng,
+    next_obstacle_distance: i32,
 }
 
 impl GamePlayer {
     fn is_on_ground(&self) -> bool {
-        self.y == 0
+        self.y_fp == 0 && self.velocity_fp == 0
+    }
+
+    pub fn pose(&self) -> PlayerPose {
+        if !self.is_on_ground() {
+            PlayerPose::Jumping
+        } else if self.is_ducked {
+            PlayerPose::Ducking
+        } else {
+            PlayerPose::Standing
+        }
     }
 
     fn handle_input(&mut self, input: Input) {
-        if !self.is_on_ground() || self.jump_tick != 0 {
+        if !self.is_on_ground() {
+            match input {
+                Input::JumpRelease => {
+                    self.is_jump_held = false;
+                }
+                Input::Duck => {
+                    self.is_jump_held = false;
+                    self.is_fast_falling = true;
+                    self.velocity_fp = self.velocity_fp.min(-FAST_FALL_VELOCITY);
+                    self.is_ducked = true;
+                }
+                Input::Unduck => {
+                    self.is_ducked = false;
+                }
+                Input::Jump | Input::None => {}
+            };
             return;
         }
 
         match input {
             Input::Jump => {
-                self.peak_jump_tick = 5;
-                self.jump_tick = 1;
+                self.velocity_fp = JUMP_VELOCITY;
+                self.is_jump_held = true;
+                self.jump_hold_ticks = 0;
                 self.is_ducked = false;
             }
             Input::Duck => {
@@ -126,58 +317,223 @@ impl GamePlayer {
             Input::Unduck => {
                 self.is_ducked = false;
             }
-            Input::None => {}
+            Input::JumpRelease | Input::None => {}
+        };
+    }
+
+    fn update_physics(&mut self) {
+        if self.is_on_ground() {
+            return;
+        }
+
+        let gravity = if self.is_fast_falling {
+            FAST_FALL_GRAVITY
+        } else if self.is_jump_held && self.jump_hold_ticks < MAX_JUMP_HOLD_TICKS {
+            self.jump_hold_ticks += 1;
+            HELD_GRAVITY
+        } else {
+            GRAVITY
         };
+
+        self.y_fp += self.velocity_fp;
+        self.velocity_fp -= gravity;
+
+        if self.y_fp <= 0 {
+            self.y_fp = 0;
+            self.velocity_fp = 0;
+            self.is_jump_held = false;
+            self.is_fast_falling = false;
+        }
+
+        self.y = self.y_fp >> FP_SHIFT;
+    }
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        GameState::new()
     }
 }
 
 impl GameState {
     pub fn new() -> GameState {
-        return GameState {
+        GameState::with_seed(0)
+    }
+
+    pub fn with_seed(seed: u64) -> GameState {
+        GameState::with_config(seed, GameConfig::default())
+    }
+
+    pub fn with_config(seed: u64, config: GameConfig) -> GameState {
+        GameState {
             score: 0,
             player: GamePlayer {
                 y: 0,
-                jump_tick: 0,
-                peak_jump_tick: 0,
+                y_fp: 0,
+                velocity_fp: 0,
+                jump_hold_ticks: 0,
+                is_jump_held: false,
+                is_fast_falling: false,
                 is_ducked: false,
-                speed: 5,
+                speed: config.difficulty.speed_at(0, 0),
             },
             obstacles: VecDeque::new(),
             tick: 0,
             is_game_over: false,
+            config,
+            combo: 0,
+            attack_points: 0,
+            incoming_obstacles: VecDeque::new(),
+            last_clear_step: 0,
+            rng: Rng::new(seed),
+            next_obstacle_distance: 0,
+        }
+    }
+
+    // Queues obstacles sent by an opponent; they replace generated obstacles from `tick` onwards
+    pub fn schedule_attack(&mut self, tick: u64, obstacles: &[GameObstacle]) {
+        for obstacle in obstacles {
+            self.incoming_obstacles.push_back((tick, obstacle.clone()));
+        }
+    }
+
+    // FNV-1a over the encoded state. Unlike `std::hash`, this is the same on every platform and
+    // build, so the server and clients can compare checksums.
+    // Queued attacks are left out, as clients can learn about them after the server does. Once
+    // spawned, they are covered by the obstacles.
+    pub fn checksum(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+
+        let mut state = self.clone();
+        state.incoming_obstacles.clear();
+        postcard::to_stdvec(&state)
+            .unwrap()
+            .iter()
+            .fold(FNV_OFFSET, |hash, byte| {
+                (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+            })
+    }
+
+    fn spawn_obstacles(&mut self) {
+        self.next_obstacle_distance -= self.player.speed as i32;
+        if self.next_obstacle_distance > 0 {
+            return;
+        }
+
+        let speed = self.player.speed;
+        let attack_ready =
+            matches!(self.incoming_obstacles.front(), Some((tick, _)) if *tick <= self.tick);
+
+        let gap = if attack_ready {
+            let (_, mut obstacle) = self.incoming_obstacles.pop_front().unwrap();
+            obstacle.position.x = SPAWN_X;
+            self.obstacles.push_back(obstacle);
+            self.config.difficulty.min_gap(speed)
+        } else {
+            let obstacle = GameObstacle::random(&mut self.rng);
+            self.obstacles.push_back(obstacle);
+            self.rng.range(
+                self.config.difficulty.min_gap(speed),
+                self.config.difficulty.max_gap(speed) + 1,
+            )
         };
+        self.next_obstacle_distance = gap as i32;
+    }
+
+    fn on_obstacle_cleared(&mut self, step: u64, near_miss: bool) {
+        if self.combo > 0 && step - self.last_clear_step <= COMBO_WINDOW_TICKS {
+            self.combo += 1;
+        } else {
+            self.combo = 1;
+        }
+        self.last_clear_step = step;
+
+        if near_miss {
+            self.attack_points += NEAR_MISS_ATTACK_POINTS;
+        }
+        if self.combo.is_multiple_of(COMBO_STEP) {
+            self.attack_points += self.combo / COMBO_STEP;
+        }
     }
 
-    fn handle_collisions(&mut self) {
+    fn handle_collisions(&mut self, step: u64) {
+        // The state is drawn once this step is done, so collide with the frames shown then
+        let frame_tick = step + 1;
         for x in &self.obstacles {
-            if is_colliding(&self.player, x) {
+            if is_colliding(&self.player, x, self.config.collision, frame_tick) {
                 self.is_game_over = true;
                 break;
             }
         }
     }
 
+    pub fn score_multiplier(&self) -> u64 {
+        let bonus_speed = self
+            .player
+            .speed
+            .saturating_sub(self.config.difficulty.initial_speed);
+        1 + (bonus_speed / SPEED_PER_MULTIPLIER) as u64
+    }
+
+    fn handle_scoring(&mut self, step: u64) {
+        let multiplier = self.score_multiplier();
+        self.score += DISTANCE_POINTS * multiplier;
+
+        let (player_x0, ..) = bounds(&self.player);
+        let mut cleared = vec![];
+        for obstacle in &mut self.obstacles {
+            if obstacle.cleared {
+                continue;
+            }
+
+            if let Some(gap) = vertical_gap(&self.player, obstacle) {
+                if gap <= NEAR_MISS_DISTANCE {
+                    obstacle.near_miss = true;
+                }
+            }
+
+            let (_, obstacle_x1, ..) = bounds(obstacle);
+            if obstacle_x1 < player_x0 {
+                obstacle.cleared = true;
+                self.score += CLEARED_POINTS * multiplier;
+                if obstacle.near_miss {
+                    self.score += NEAR_MISS_POINTS * multiplier;
+                }
+                cleared.push(obstacle.near_miss);
+            }
+        }
+
+        for near_miss in cleared {
+            self.on_obstacle_cleared(step, near_miss);
+        }
+    }
+
     pub fn tick(&mut self, input: Input) {
         if self.is_game_over {
             return;
         }
 
-        self.player.handle_input(input);
-
-        if self.player.jump_tick < self.player.peak_jump_tick {
-            self.player.y += 10;
-            self.player.jump_tick += 1;
-        } else if self.player.jump_tick + 1 < self.player.peak_jump_tick * 2 {
-            self.player.y -= 10;
-            self.player.jump_tick += 1;
-        } else {
-            self.player.y = 0;
-            self.player.jump_tick = 0;
-            self.player.peak_jump_tick = 0;
+        // Inputs only land on the first step of a tick
+        let steps = self.config.steps_per_tick();
+        for i in 0..steps {
+            let input = if i == 0 { input } else { Input::None };
+            self.step(self.tick * steps + i, input);
+            if self.is_game_over {
+                break;
+            }
         }
 
+        self.tick += 1;
+    }
+
+    fn step(&mut self, step: u64, input: Input) {
+        self.player.speed = self.config.difficulty.speed_at(step, self.score);
+        self.player.handle_input(input);
+        self.player.update_physics();
+
         while let Some(x) = self.obstacles.front() {
-            if x.position.x < -(x.collision_box().w as i32) {
+            if x.position.x < -(x.bounding_box().w as i32) {
                 self.obstacles.pop_front();
                 continue;
             }
@@ -185,21 +541,216 @@ impl GameState {
         }
 
         for x in &mut self.obstacles {
-            x.position.x = x.position.x - (self.player.speed as i32);
+            x.position.x -= self.player.speed as i32;
         }
 
-        while self.obstacles.len() < 16 {
-            self.obstacles.push_back(GameObstacle {
-                category: GameObstacleCategory::Bird,
-                position: Position {
-                    x: (512 + 100 * self.obstacles.len()) as i32,
-                    y: 70,
-                },
-            })
+        self.spawn_obstacles();
+
+        self.handle_collisions(step);
+        if !self.is_game_over {
+            self.handle_scoring(step);
         }
+    }
+}
 
-        self.handle_collisions();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        self.tick += 1;
+    fn run(mut state: GameState, ticks: u64) -> GameState {
+        for _ in 0..ticks {
+            state.tick(Input::None);
+        }
+        state
+    }
+
+    #[test]
+    fn seeded_games_are_deterministic() {
+        let first = run(GameState::with_seed(5), 400);
+        let second = run(GameState::with_seed(5), 400);
+        assert_eq!(first.checksum(), second.checksum());
+        assert_eq!(
+            format!("{:?}", first.obstacles),
+            format!("{:?}", second.obstacles)
+        );
+
+        let other = run(GameState::with_seed(6), 400);
+        assert_ne!(
+            format!("{:?}", first.obstacles),
+            format!("{:?}", other.obstacles)
+        );
+    }
+
+    #[test]
+    fn collisions_use_the_drawn_frame() {
+        // A state ticked from tick 2 is drawn at tick 3, the first tick of the second run frame
+        let drawn = player_mask(PlayerPose::Standing, 3);
+        let previous = player_mask(PlayerPose::Standing, 2);
+        assert_ne!(drawn.rows, previous.rows);
+
+        // Birds keep their first frame until tick 5, so only the player's frame changes
+        let bird = obstacle_mask(GameObstacleCategory::Bird, 3);
+        let overlaps = |player, (x, y)| masks_overlap(player, PLAYER_X, 0, bird, x, y);
+        let (x, y) = (PLAYER_X - bird.width as i32..PLAYER_X + drawn.width as i32)
+            .flat_map(|x| (-(bird.height as i32)..drawn.height as i32).map(move |y| (x, y)))
+            .find(|position| overlaps(drawn, *position) != overlaps(previous, *position))
+            .expect("run frames should differ somewhere");
+
+        let mut state = GameState::with_config(
+            0,
+            GameConfig {
+                collision: CollisionMode::PixelMask,
+                ..GameConfig::default()
+            },
+        );
+        state.tick = 2;
+        state.next_obstacle_distance = i32::MAX;
+        let speed = state.config.difficulty.speed_at(2, 0) as i32;
+        state.obstacles.push_back(GameObstacle {
+            category: GameObstacleCategory::Bird,
+            position: Position { x: x + speed, y },
+            cleared: false,
+            near_miss: false,
+        });
+
+        state.tick(Input::None);
+        assert_eq!(state.is_game_over, overlaps(drawn, (x, y)));
+    }
+
+    #[test]
+    fn slower_tick_rates_take_several_steps() {
+        let slow_config = GameConfig {
+            tick_rate: PHYSICS_TICK_RATE / 4,
+            ..GameConfig::default()
+        };
+        let mut fast = GameState::with_seed(5);
+        let mut slow = GameState::with_config(5, slow_config);
+        for tick in 0..100 {
+            let input = if tick % 10 == 0 {
+                Input::Jump
+            } else {
+                Input::None
+            };
+            slow.tick(input);
+            fast.tick(input);
+            for _ in 1..4 {
+                fast.tick(Input::None);
+            }
+
+            assert_eq!(slow.is_game_over, fast.is_game_over);
+            assert_eq!(slow.score, fast.score);
+            assert_eq!(slow.player.y, fast.player.y);
+            assert_eq!(
+                format!("{:?}", slow.obstacles),
+                format!("{:?}", fast.obstacles)
+            );
+            if slow.is_game_over {
+                break;
+            }
+            assert_eq!(slow.tick * 4, fast.tick);
+        }
+    }
+
+    // Heights of the player after each tick, running `inputs` and then nothing until it lands
+    fn jump(inputs: &[Input]) -> (GamePlayer, Vec<i32>) {
+        let mut player = GameState::new().player;
+        let mut heights = vec![];
+        for tick in 0..200 {
+            player.handle_input(inputs.get(tick).copied().unwrap_or(Input::None));
+            player.update_physics();
+            heights.push(player.y);
+            if tick >= inputs.len() && player.is_on_ground() {
+                break;
+            }
+        }
+        (player, heights)
+    }
+
+    fn peak(heights: &[i32]) -> i32 {
+        heights.iter().copied().max().unwrap()
+    }
+
+    #[test]
+    fn tapped_jump_peaks_lower() {
+        let (_, held) = jump(&[Input::Jump]);
+        let (_, tapped) = jump(&[Input::Jump, Input::JumpRelease]);
+        assert!(peak(&tapped) < peak(&held));
+        assert!(tapped.len() < held.len());
+    }
+
+    #[test]
+    fn fast_fall_lands_sooner() {
+        let mut inputs = vec![Input::Jump, Input::None, Input::None, Input::None];
+        let (_, normal) = jump(&inputs);
+        inputs.push(Input::Duck);
+        let (player, fast) = jump(&inputs);
+        assert!(fast.len() < normal.len());
+        assert!(peak(&fast) <= peak(&normal));
+        // Still ducking after landing, as duck was never released
+        assert!(player.is_ducked);
+    }
+
+    #[test]
+    fn lands_on_the_ground() {
+        for inputs in [
+            vec![Input::Jump],
+            vec![Input::Jump, Input::JumpRelease],
+            vec![Input::Jump, Input::None, Input::Duck, Input::Unduck],
+        ] {
+            let (player, heights) = jump(&inputs);
+            assert!(heights[..heights.len() - 1].iter().all(|y| *y > 0));
+            assert_eq!(heights.last(), Some(&0));
+            assert_eq!((player.y, player.y_fp, player.velocity_fp), (0, 0, 0));
+            assert!(!player.is_jump_held && !player.is_fast_falling);
+            assert_eq!(player.pose(), PlayerPose::Standing);
+        }
+    }
+
+    #[test]
+    fn mid_air_jump_is_ignored() {
+        let (_, single) = jump(&[Input::Jump, Input::None, Input::None]);
+        let (_, double) = jump(&[Input::Jump, Input::None, Input::Jump]);
+        assert_eq!(single, double);
+    }
+
+    #[test]
+    fn ducking_on_the_ground() {
+        let mut player = GameState::new().player;
+        player.handle_input(Input::Duck);
+        player.update_physics();
+        assert_eq!(player.pose(), PlayerPose::Ducking);
+        assert_eq!(player.y, 0);
+
+        // Jumping stands the player back up
+        player.handle_input(Input::Jump);
+        player.update_physics();
+        assert_eq!(player.pose(), PlayerPose::Jumping);
+        assert!(!player.is_ducked);
+    }
+
+    #[test]
+    fn checksum_ignores_queued_attacks() {
+        let state = run(GameState::with_seed(3), 10);
+        let mut attacked = state.clone();
+        let obstacle = GameObstacle::random(&mut Rng::new(1));
+        attacked.schedule_attack(state.tick + 5, &[obstacle]);
+        assert_eq!(attacked.checksum(), state.checksum());
+
+        // Ticking still changes the checksum
+        let next = run(state.clone(), 1);
+        assert_ne!(next.checksum(), state.checksum());
+
+        // Once spawned, the attack is part of the obstacles and so of the checksum
+        let (mut state, mut attacked) = (state, attacked);
+        while attacked.incoming_obstacles.len() == 1 && !attacked.is_game_over {
+            state.tick(Input::None);
+            attacked.tick(Input::None);
+            if attacked.incoming_obstacles.is_empty() {
+                assert_ne!(attacked.checksum(), state.checksum());
+            } else {
+                assert_eq!(attacked.checksum(), state.checksum());
+            }
+        }
+        assert!(attacked.incoming_obstacles.is_empty());
     }
 }
@@ -0,0 +1,59 @@
+use crate::game::{GameObstacleCategory, PlayerPose};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+// Rectangle relative to the bottom-left corner of an entity's sprite. Boxes are kept slightly
+// inside the visible pixels so that grazing an obstacle is forgiven, like the original game.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Hitbox {
+    pub x: i32,
+    pub y: i32,
+    pub w: u32,
+    pub h: u32,
+}
+
+const fn hitbox(x: i32, y: i32, w: u32, h: u32) -> Hitbox {
+    Hitbox { x, y, w, h }
+}
+
+const STANDING: [Hitbox; 3] = [
+    hitbox(22, 32, 20, 14),
+    hitbox(6, 12, 28, 17),
+    hitbox(10, 0, 12, 12),
+];
+
+const JUMPING: [Hitbox; 3] = [
+    hitbox(22, 32, 20, 14),
+    hitbox(6, 12, 28, 17),
+    hitbox(10, 4, 12, 8),
+];
+
+const DUCKING: [Hitbox; 2] = [hitbox(2, 10, 55, 16), hitbox(12, 0, 14, 10)];
+
+const CACTUS: [Hitbox; 2] = [hitbox(5, 0, 7, 34), hitbox(1, 14, 15, 12)];
+
+const LARGE_CACTUS: [Hitbox; 2] = [hitbox(8, 0, 9, 49), hitbox(1, 18, 23, 18)];
+
+const BIRD: [Hitbox; 3] = [
+    hitbox(2, 10, 14, 4),
+    hitbox(16, 2, 28, 10),
+    hitbox(10, 12, 18, 8),
+];
+
+pub fn player_hitboxes(pose: PlayerPose) -> &'static [Hitbox] {
+    match pose {
+        PlayerPose::Standing => &STANDING,
+        PlayerPose::Jumping => &JUMPING,
+        PlayerPose::Ducking => &DUCKING,
+    }
+}
+
+pub fn obstacle_hitboxes(category: GameObstacleCategory) -> &'static [Hitbox] {
+    match category {
+        GameObstacleCategory::Cactus => &CACTUS,
+        GameObstacleCategory::LargeCactus => &LARGE_CACTUS,
+        GameObstacleCategory::Bird => &BIRD,
+    }
+}
@@ -6,6 +6,7 @@ use wasm_bindgen::prelude::*;
 #[derive(Eq, PartialEq, Copy, Clone, Serialize, Deserialize, Debug)]
 pub enum Input {
     Jump,
+    JumpRelease,
     Duck,
     Unduck,
     None,
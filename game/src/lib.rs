@@ -1,3 +1,10 @@
+pub mod codec;
+pub mod difficulty;
 pub mod game;
+pub mod hitbox;
 pub mod input;
-pub mod messages;
\ No newline at end of file
+pub mod mask;
+pub mod messages;
+pub mod replay;
+pub mod rng;
+pub mod rollback;
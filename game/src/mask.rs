@@ -0,0 +1,155 @@
+use crate::game::{GameObstacleCategory, PlayerPose};
+
+// 1-bit collision masks generated by build.rs from frontend/static/sprites. Rows are stored
+// bottom-up and bit 0 of each row is the leftmost pixel.
+pub struct SpriteMask {
+    pub width: u32,
+    pub height: u32,
+    pub rows: &'static [u64],
+}
+
+mod sprites {
+    use super::SpriteMask;
+
+    include!(concat!(env!("OUT_DIR"), "/sprite_masks.rs"));
+}
+
+// Ticks each animation frame is shown for, matching the frame times used by the frontend
+pub const RUN_FRAME_TICKS: u64 = 3;
+pub const BIRD_FRAME_TICKS: u64 = 5;
+
+fn animation_frame(
+    frames: [&'static SpriteMask; 2],
+    frame_ticks: u64,
+    tick: u64,
+) -> &'static SpriteMask {
+    frames[((tick / frame_ticks) % 2) as usize]
+}
+
+pub fn player_mask(pose: PlayerPose, tick: u64) -> &'static SpriteMask {
+    match pose {
+        PlayerPose::Standing | PlayerPose::Jumping if tick == 0 => &sprites::STAND,
+        PlayerPose::Standing | PlayerPose::Jumping => {
+            animation_frame([&sprites::RUN1, &sprites::RUN2], RUN_FRAME_TICKS, tick)
+        }
+        PlayerPose::Ducking => {
+            animation_frame([&sprites::DUCK1, &sprites::DUCK2], RUN_FRAME_TICKS, tick)
+        }
+    }
+}
+
+pub fn obstacle_mask(category: GameObstacleCategory, tick: u64) -> &'static SpriteMask {
+    match category {
+        GameObstacleCategory::Cactus => &sprites::SMALL_CACTUS1,
+        GameObstacleCategory::LargeCactus => &sprites::LARGE_CACTUS1,
+        GameObstacleCategory::Bird => {
+            animation_frame([&sprites::BIRD1, &sprites::BIRD2], BIRD_FRAME_TICKS, tick)
+        }
+    }
+}
+
+// Checks whether any opaque pixels overlap when the bottom-left corners of the masks are placed
+// at (ax, ay) and (bx, by)
+pub fn masks_overlap(a: &SpriteMask, ax: i32, ay: i32, b: &SpriteMask, bx: i32, by: i32) -> bool {
+    let dx = bx - ax;
+    if dx >= a.width as i32 || -dx >= b.width as i32 {
+        return false;
+    }
+
+    let y0 = ay.max(by);
+    let y1 = (ay + a.height as i32).min(by + b.height as i32);
+
+    (y0..y1).any(|y| {
+        let a_row = a.rows[(y - ay) as usize];
+        let b_row = b.rows[(y - by) as usize];
+
+        if dx >= 0 {
+            a_row & (b_row << dx) != 0
+        } else {
+            (a_row << -dx) & b_row != 0
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Full bottom row with a single pixel above its left end
+    const L_SHAPE: SpriteMask = SpriteMask {
+        width: 3,
+        height: 2,
+        rows: &[0b111, 0b001],
+    };
+    const DOT: SpriteMask = SpriteMask {
+        width: 1,
+        height: 1,
+        rows: &[0b1],
+    };
+    const BAR: SpriteMask = SpriteMask {
+        width: 2,
+        height: 1,
+        rows: &[0b11],
+    };
+
+    #[test]
+    fn horizontal_offsets() {
+        assert!(masks_overlap(&L_SHAPE, 0, 0, &DOT, 0, 0));
+        assert!(masks_overlap(&L_SHAPE, 0, 0, &DOT, 2, 0));
+        assert!(!masks_overlap(&L_SHAPE, 0, 0, &DOT, 3, 0));
+
+        // The bar's right pixel reaches the shape's left edge
+        assert!(masks_overlap(&L_SHAPE, 0, 0, &BAR, -1, 0));
+        assert!(!masks_overlap(&L_SHAPE, 0, 0, &BAR, -2, 0));
+
+        // Same as above with the masks swapped
+        assert!(masks_overlap(&DOT, 2, 0, &L_SHAPE, 0, 0));
+        assert!(!masks_overlap(&DOT, 3, 0, &L_SHAPE, 0, 0));
+        assert!(masks_overlap(&BAR, 5, 3, &L_SHAPE, 6, 3));
+    }
+
+    #[test]
+    fn vertical_offsets() {
+        assert!(masks_overlap(&L_SHAPE, 0, 0, &DOT, 0, 1));
+        assert!(!masks_overlap(&L_SHAPE, 0, 0, &DOT, 1, 1));
+        assert!(!masks_overlap(&L_SHAPE, 0, 0, &DOT, 0, 2));
+        assert!(!masks_overlap(&L_SHAPE, 0, 0, &DOT, 0, -1));
+        assert!(masks_overlap(&L_SHAPE, 10, -4, &BAR, 9, -3));
+        assert!(!masks_overlap(&L_SHAPE, 10, -4, &BAR, 11, -3));
+        assert!(!masks_overlap(&L_SHAPE, 10, -4, &BAR, 9, -2));
+    }
+
+    #[test]
+    fn sprites_overlap_themselves() {
+        for mask in [
+            &sprites::RUN1,
+            &sprites::DUCK1,
+            &sprites::BIRD1,
+            &sprites::LARGE_CACTUS1,
+        ] {
+            assert!(masks_overlap(mask, 0, 0, mask, 0, 0));
+            assert!(!masks_overlap(mask, 0, 0, mask, mask.width as i32, 0));
+            assert!(!masks_overlap(mask, 0, 0, mask, 0, mask.height as i32));
+        }
+    }
+
+    fn same(a: &SpriteMask, b: &SpriteMask) -> bool {
+        a.width == b.width && a.height == b.height && a.rows == b.rows
+    }
+
+    #[test]
+    fn frames_follow_ticks() {
+        let run = |tick| player_mask(PlayerPose::Standing, tick);
+        assert!(same(run(0), &sprites::STAND));
+        assert!(same(run(1), &sprites::RUN1));
+        assert!(same(run(2), &sprites::RUN1));
+        assert!(same(run(3), &sprites::RUN2));
+        assert!(same(run(6), &sprites::RUN1));
+        assert!(same(player_mask(PlayerPose::Ducking, 0), &sprites::DUCK1));
+
+        let bird = |tick| obstacle_mask(GameObstacleCategory::Bird, tick);
+        assert!(same(bird(4), &sprites::BIRD1));
+        assert!(same(bird(5), &sprites::BIRD2));
+        assert!(same(bird(10), &sprites::BIRD1));
+    }
+}
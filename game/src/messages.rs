@@ -1,8 +1,19 @@
+use crate::game::{GameConfig, GameObstacle, GameState};
 use crate::input::Input;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+// How often the server includes game checksums in tick events
+pub const CHECKSUM_INTERVAL_TICKS: u64 = 20;
+// Upper bounds for lobby settings picked by clients. Larger values would overflow the deadlines
+// computed from them or the indices of players in compact tick events.
+pub const MAX_PLAYERS: u32 = 99;
+pub const MAX_FILL_TIMEOUT_SECS: u64 = 10 * 60;
+pub const MAX_COUNTDOWN_SECS: u64 = 60;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum PlayerState {
     Playing,
     Dead,
@@ -14,28 +25,187 @@ pub struct PlayerInfo {
     pub username: String,
     pub id: Uuid,
     pub state: PlayerState,
+    #[serde(default)]
+    pub placement: Option<u32>,
+    #[serde(default)]
+    pub is_ready: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Standing {
+    pub player: Uuid,
+    pub username: String,
+    pub placement: u32,
+    pub score: u64,
+    pub badges: u32,
 }
 
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum LobbyState {
     Waiting,
+    Countdown,
     InPlay,
     Ended,
 }
 
+// Settings left out when creating a lobby take their default value
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LobbyConfig {
+    pub min_players: u32,
+    pub max_players: u32,
+    // Once `min_players` have joined, the countdown starts after this many seconds, or right
+    // away when the lobby fills up
+    pub fill_timeout_secs: u64,
+    pub countdown_secs: u64,
+    // Private lobbies have a host and only start once the host starts them or everyone is ready
+    pub is_private: bool,
+    pub game: GameConfig,
+    // Picked at random for every game if not set
+    pub seed: Option<u64>,
+}
+
+impl Default for LobbyConfig {
+    fn default() -> Self {
+        LobbyConfig {
+            min_players: 2,
+            max_players: MAX_PLAYERS,
+            fill_timeout_secs: 30,
+            countdown_secs: 5,
+            is_private: false,
+            game: GameConfig::default(),
+            seed: None,
+        }
+    }
+}
+
+impl LobbyConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min_players == 0
+            || self.max_players < self.min_players
+            || self.max_players > MAX_PLAYERS
+        {
+            return Err("Invalid player limits!".to_string());
+        }
+        if self.fill_timeout_secs > MAX_FILL_TIMEOUT_SECS {
+            return Err("Fill timeout is too long!".to_string());
+        }
+        if self.countdown_secs > MAX_COUNTDOWN_SECS {
+            return Err("Countdown is too long!".to_string());
+        }
+        self.game.validate()
+    }
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TargetingStrategy {
+    #[default]
+    Random,
+    Attackers,
+    KOs,
+    Badges,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum C2SMessage {
-    LobbyJoinRequest { name: String, lobby_id: String },
-    GameInput { tick: u64, input: Input },
+    LobbyJoinRequest {
+        name: String,
+        lobby_id: String,
+        // Only used if this request creates the lobby
+        #[serde(default)]
+        config: Option<LobbyConfig>,
+        // Identifies the player across games for ratings. Players without one are issued one in
+        // `LobbyJoinSuccess`.
+        #[serde(default)]
+        player_token: Option<Uuid>,
+    },
+    // Watches an existing lobby without taking part in the game
+    SpectateRequest {
+        name: String,
+        lobby_id: String,
+    },
+    // Joins whichever public lobby the matchmaker picks
+    QueueForMatch {
+        name: String,
+        #[serde(default)]
+        player_token: Option<Uuid>,
+    },
+    Resume {
+        lobby_id: String,
+        session_token: Uuid,
+    },
+    GameInput {
+        tick: u64,
+        input: Input,
+    },
+    SetTargetingStrategy {
+        strategy: TargetingStrategy,
+    },
+    SetReady {
+        is_ready: bool,
+    },
+    // Host only
+    StartGame,
+    KickPlayer {
+        player: Uuid,
+    },
+    ChangeLobbySettings {
+        config: LobbyConfig,
+    },
+    TransferHost {
+        player: Uuid,
+    },
+    // Sent when a checksum from the server does not match the client's copy of a game
+    DesyncReport {
+        tick: u64,
+        player: Uuid,
+        expected: u64,
+        actual: u64,
+    },
+    // Asks for the current state of the given games, answered with a `SnapshotEvent`
+    SnapshotRequest {
+        players: Vec<Uuid>,
+    },
+    // Answers a `PingEvent` right away
+    Pong {
+        id: u64,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum S2CMessage {
     LobbyJoinSuccess {
+        lobby_id: String,
         player_id: Uuid,
+        session_token: Uuid,
+        // To be kept by the client and sent with every later join
+        player_token: Uuid,
+        config: LobbyConfig,
+        host: Option<Uuid>,
+        players: Vec<PlayerInfo>,
+    },
+    ResumeSuccess {
+        player_id: Uuid,
+        session_token: Uuid,
+        lobby_state: LobbyState,
+        config: LobbyConfig,
+        host: Option<Uuid>,
+        players: Vec<PlayerInfo>,
+        game_states: Vec<(Uuid, GameState)>,
+        #[serde(default)]
+        player_order: Vec<Uuid>,
+    },
+    SpectateSuccess {
+        spectator_id: Uuid,
+        lobby_state: LobbyState,
+        config: LobbyConfig,
         players: Vec<PlayerInfo>,
+        game_states: Vec<(Uuid, GameState)>,
+        #[serde(default)]
+        player_order: Vec<Uuid>,
     },
     LobbyJoinFailureResponse {
         reason: String,
@@ -43,14 +213,163 @@ pub enum S2CMessage {
     LobbyJoinEvent {
         player: PlayerInfo,
     },
+    LobbyClosedEvent,
+    KickedEvent,
+    PlayerReadyEvent {
+        player: Uuid,
+        is_ready: bool,
+    },
+    HostChangeEvent {
+        host: Uuid,
+    },
+    LobbySettingsChangeEvent {
+        config: LobbyConfig,
+    },
+    LobbyLeaveEvent {
+        player: Uuid,
+    },
+    CountdownEvent {
+        seconds_remaining: u64,
+    },
     LobbyStateChangeEvent {
         new_state: LobbyState,
     },
+    GameStartEvent {
+        seed: u64,
+        config: GameConfig,
+        // Players of the game, in the order `CompactGameTickEvent`s refer to them by
+        #[serde(default)]
+        player_order: Vec<Uuid>,
+    },
     GameTickEvent {
         tick: u64,
         players: Vec<(Uuid, Input)>,
+        // Checksums of every game after this tick, only sent every `CHECKSUM_INTERVAL_TICKS`
+        #[serde(default)]
+        checksums: Vec<(Uuid, u64)>,
+    },
+    // `GameTickEvent` for binary connections, with players referred to by their index
+    CompactGameTickEvent {
+        tick: u64,
+        players: Vec<(u16, Input)>,
+        checksums: Vec<(u16, u64)>,
+    },
+    TargetChangeEvent {
+        player: Uuid,
+        target: Option<Uuid>,
+        strategy: TargetingStrategy,
+    },
+    BadgeEvent {
+        player: Uuid,
+        badges: u32,
+    },
+    PlayerEliminatedEvent {
+        player: Uuid,
+        tick: u64,
+        placement: u32,
+        eliminated_by: Option<Uuid>,
+    },
+    GameOverEvent {
+        standings: Vec<Standing>,
+        replay_id: Option<Uuid>,
+    },
+    AttackEvent {
+        attacker: Uuid,
+        target: Uuid,
+        tick: u64,
+        obstacles: Vec<GameObstacle>,
+    },
+    LeaderboardEvent {
+        tick: u64,
+        scores: Vec<(Uuid, u64)>,
+    },
+    // Sent to players regularly to measure their latency
+    PingEvent {
+        id: u64,
+    },
+    // Sent after each ping. To have their inputs arrive in time, players should run
+    // `input_delay` ticks ahead of `tick`, the tick the server was on when it sent this.
+    TimeSyncEvent {
+        tick: u64,
+        input_delay: u64,
+        rtt_ms: u64,
+    },
+    // Sent to a player for every input, telling them whether it arrived in time to be applied
+    InputAckEvent {
+        tick: u64,
+        accepted: bool,
+    },
+    SnapshotEvent {
+        game_states: Vec<(Uuid, GameState)>,
     },
     InvalidMessage {
         error: String,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::difficulty::DifficultyConfig;
+    use crate::game::PHYSICS_TICK_RATE;
+
+    fn check(change: impl FnOnce(&mut LobbyConfig)) -> Result<(), String> {
+        let mut config = LobbyConfig::default();
+        change(&mut config);
+        config.validate()
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        assert!(check(|_| {}).is_ok());
+        for difficulty in [
+            DifficultyConfig::easy(),
+            DifficultyConfig::normal(),
+            DifficultyConfig::hard(),
+        ] {
+            assert!(check(|config| config.game.difficulty = difficulty).is_ok());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_player_limits() {
+        assert!(check(|config| config.min_players = 0).is_err());
+        assert!(check(|config| config.max_players = 1).is_err());
+        assert!(check(|config| config.max_players = MAX_PLAYERS + 1).is_err());
+        assert!(check(|config| {
+            config.min_players = MAX_PLAYERS;
+            config.max_players = MAX_PLAYERS;
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_long_waits() {
+        assert!(check(|config| config.countdown_secs = u64::MAX).is_err());
+        assert!(check(|config| config.fill_timeout_secs = u64::MAX).is_err());
+        assert!(check(|config| {
+            config.countdown_secs = MAX_COUNTDOWN_SECS;
+            config.fill_timeout_secs = MAX_FILL_TIMEOUT_SECS;
+        })
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_difficulty() {
+        assert!(check(|config| config.game.difficulty.initial_speed = 0).is_err());
+        assert!(check(|config| config.game.difficulty.max_speed = u32::MAX).is_err());
+        assert!(check(|config| config.game.difficulty.base_gap = u32::MAX).is_err());
+        assert!(check(|config| config.game.difficulty.gap_per_speed = u32::MAX).is_err());
+        assert!(check(|config| config.game.difficulty.gap_variance = u32::MAX).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_tick_rates() {
+        assert!(check(|config| config.game.tick_rate = 0).is_err());
+        assert!(check(|config| config.game.tick_rate = 30).is_err());
+        assert!(check(|config| config.game.tick_rate = PHYSICS_TICK_RATE * 2).is_err());
+        for tick_rate in [1, 5, 10, PHYSICS_TICK_RATE] {
+            assert!(check(|config| config.game.tick_rate = tick_rate).is_ok());
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::game::{GameConfig, GameObstacle, GameState};
+use crate::input::Input;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+// Replay files start with these bytes, followed by the postcard encoded `Replay`
+pub const REPLAY_MAGIC: [u8; 4] = *b"DR99";
+// Bumped whenever the layout of `Replay` changes
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    // Version of the game crate that recorded the replay, as the simulation may change
+    pub game_version: String,
+    pub seed: u64,
+    pub config: GameConfig,
+    // Players are referred to by their index in this list
+    pub players: Vec<(Uuid, String)>,
+}
+
+// Everything that happened on a tick besides players running into obstacles. Ticks without
+// any events are not stored.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReplayTick {
+    pub tick: u64,
+    pub inputs: Vec<(u32, Input)>,
+    pub forfeits: Vec<u32>,
+    // (target, tick the obstacles arrive on, obstacles)
+    pub attacks: Vec<(u32, u64, Vec<GameObstacle>)>,
+}
+
+impl ReplayTick {
+    fn is_empty(&self) -> bool {
+        self.inputs.is_empty() && self.forfeits.is_empty() && self.attacks.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub ticks: Vec<ReplayTick>,
+    pub length: u64,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    Decode(postcard::Error),
+    // Decoded, but refers to players or ticks that do not exist
+    Invalid(String),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::BadMagic => write!(f, "not a replay file"),
+            ReplayError::UnsupportedVersion(version) => {
+                write!(f, "unsupported replay version {}", version)
+            }
+            ReplayError::Decode(e) => write!(f, "failed to decode replay: {}", e),
+            ReplayError::Invalid(reason) => write!(f, "invalid replay: {}", reason),
+        }
+    }
+}
+
+impl Replay {
+    pub fn new(seed: u64, config: GameConfig, players: Vec<(Uuid, String)>) -> Replay {
+        Replay {
+            header: ReplayHeader {
+                version: REPLAY_VERSION,
+                game_version: env!("CARGO_PKG_VERSION").to_string(),
+                seed,
+                config,
+                players,
+            },
+            ticks: vec![],
+            length: 0,
+        }
+    }
+
+    pub fn player_index(&self, uuid: &Uuid) -> Option<u32> {
+        self.header
+            .players
+            .iter()
+            .position(|(id, _)| id == uuid)
+            .map(|idx| idx as u32)
+    }
+
+    pub fn record_tick(&mut self, tick: ReplayTick) {
+        self.length = tick.tick + 1;
+        if !tick.is_empty() {
+            self.ticks.push(tick);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend(postcard::to_stdvec(self).unwrap());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Replay, ReplayError> {
+        let body = bytes
+            .strip_prefix(&REPLAY_MAGIC)
+            .ok_or(ReplayError::BadMagic)?;
+        let replay: Replay = postcard::from_bytes(body).map_err(ReplayError::Decode)?;
+        if replay.header.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.header.version));
+        }
+        replay.validate()?;
+        Ok(replay)
+    }
+
+    // `ReplayPlayer` trusts the replay, so anything it would index with is checked here
+    fn validate(&self) -> Result<(), ReplayError> {
+        let players = self.header.players.len() as u32;
+        let mut previous_tick = None;
+        for tick in &self.ticks {
+            if previous_tick.is_some_and(|previous| tick.tick <= previous) {
+                return Err(ReplayError::Invalid(format!(
+                    "tick {} is out of order",
+                    tick.tick
+                )));
+            }
+            if tick.tick >= self.length {
+                return Err(ReplayError::Invalid(format!(
+                    "tick {} is past the end",
+                    tick.tick
+                )));
+            }
+            previous_tick = Some(tick.tick);
+
+            let mut indices = tick
+                .inputs
+                .iter()
+                .map(|(player, _)| *player)
+                .chain(tick.forfeits.iter().copied())
+                .chain(tick.attacks.iter().map(|(target, ..)| *target));
+            if let Some(player) = indices.find(|player| *player >= players) {
+                return Err(ReplayError::Invalid(format!(
+                    "unknown player {} on tick {}",
+                    player, tick.tick
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Re-simulates a replay one tick at a time, in the same order the server does
+pub struct ReplayPlayer {
+    replay: Replay,
+    states: Vec<GameState>,
+    tick: u64,
+    next_event: usize,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        let state = GameState::with_config(replay.header.seed, replay.header.config);
+        ReplayPlayer {
+            states: vec![state; replay.header.players.len()],
+            replay,
+            tick: 0,
+            next_event: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.tick >= self.replay.length
+    }
+
+    pub fn header(&self) -> &ReplayHeader {
+        &self.replay.header
+    }
+
+    pub fn step(&mut self) {
+        if self.is_finished() {
+            return;
+        }
+
+        let events = match self.replay.ticks.get(self.next_event) {
+            Some(events) if events.tick == self.tick => {
+                self.next_event += 1;
+                events.clone()
+            }
+            _ => ReplayTick::default(),
+        };
+
+        for (idx, state) in self.states.iter_mut().enumerate() {
+            let input = events
+                .inputs
+                .iter()
+                .find(|(player, _)| *player as usize == idx)
+                .map_or(Input::None, |(_, input)| *input);
+            state.tick(input);
+        }
+        for player in &events.forfeits {
+            self.states[*player as usize].is_game_over = true;
+        }
+        for (target, tick, obstacles) in &events.attacks {
+            self.states[*target as usize].schedule_attack(*tick, obstacles);
+        }
+
+        self.tick += 1;
+    }
+
+    pub fn states(&self) -> impl Iterator<Item = (Uuid, &GameState)> {
+        self.replay
+            .header
+            .players
+            .iter()
+            .map(|(uuid, _)| *uuid)
+            .zip(self.states.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    const SEED: u64 = 42;
+
+    // Plays a short game between two players, returning the replay and the final states
+    fn record() -> (Replay, Vec<GameState>) {
+        let players = vec![
+            (Uuid::from_u128(1), "first".to_string()),
+            (Uuid::from_u128(2), "second".to_string()),
+        ];
+        let mut replay = Replay::new(SEED, GameConfig::default(), players);
+        let mut states = vec![GameState::with_config(SEED, GameConfig::default()); 2];
+        let mut rng = Rng::new(7);
+
+        for tick in 0..300 {
+            let mut events = ReplayTick {
+                tick,
+                ..ReplayTick::default()
+            };
+            if tick % 40 == 0 {
+                events.inputs.push((0, Input::Jump));
+            }
+            if tick % 55 == 10 {
+                events.inputs.push((1, Input::Duck));
+            }
+            if tick % 55 == 30 {
+                events.inputs.push((1, Input::Unduck));
+            }
+            if tick == 100 {
+                events
+                    .attacks
+                    .push((1, tick + 20, vec![GameObstacle::random(&mut rng)]));
+            }
+            if tick == 250 {
+                events.forfeits.push(0);
+            }
+
+            for (idx, state) in states.iter_mut().enumerate() {
+                let input = events
+                    .inputs
+                    .iter()
+                    .find(|(player, _)| *player as usize == idx)
+                    .map_or(Input::None, |(_, input)| *input);
+                state.tick(input);
+            }
+            for player in &events.forfeits {
+                states[*player as usize].is_game_over = true;
+            }
+            for (target, tick, obstacles) in &events.attacks {
+                states[*target as usize].schedule_attack(*tick, obstacles);
+            }
+            replay.record_tick(events);
+        }
+
+        (replay, states)
+    }
+
+    #[test]
+    fn empty_ticks_are_not_stored() {
+        let (replay, _) = record();
+        assert_eq!(replay.length, 300);
+        assert!(replay.ticks.iter().all(|tick| !tick.is_empty()));
+        assert!(replay.ticks.len() < 300);
+    }
+
+    #[test]
+    fn round_trip() {
+        let (replay, _) = record();
+        let bytes = replay.to_bytes();
+        assert!(bytes.starts_with(&REPLAY_MAGIC));
+
+        let decoded = Replay::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.length, replay.length);
+        assert_eq!(decoded.ticks.len(), replay.ticks.len());
+        assert_eq!(decoded.header.seed, SEED);
+        assert_eq!(decoded.header.players, replay.header.players);
+        assert_eq!(decoded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let (mut replay, _) = record();
+        let bytes = replay.to_bytes();
+
+        assert!(matches!(
+            Replay::from_bytes(&bytes[4..]),
+            Err(ReplayError::BadMagic)
+        ));
+        assert!(matches!(
+            Replay::from_bytes(&bytes[..bytes.len() / 2]),
+            Err(ReplayError::Decode(_))
+        ));
+
+        let mut unknown_player = replay.clone();
+        unknown_player.ticks[0].inputs.push((2, Input::Jump));
+        assert!(matches!(
+            Replay::from_bytes(&unknown_player.to_bytes()),
+            Err(ReplayError::Invalid(_))
+        ));
+
+        let mut unknown_target = replay.clone();
+        unknown_target.ticks[0].attacks.push((7, 10, vec![]));
+        assert!(matches!(
+            Replay::from_bytes(&unknown_target.to_bytes()),
+            Err(ReplayError::Invalid(_))
+        ));
+
+        let mut out_of_order = replay.clone();
+        out_of_order.ticks.swap(0, 1);
+        assert!(matches!(
+            Replay::from_bytes(&out_of_order.to_bytes()),
+            Err(ReplayError::Invalid(_))
+        ));
+
+        let mut truncated = replay.clone();
+        truncated.length = truncated.ticks.last().unwrap().tick;
+        assert!(matches!(
+            Replay::from_bytes(&truncated.to_bytes()),
+            Err(ReplayError::Invalid(_))
+        ));
+
+        replay.header.version = REPLAY_VERSION + 1;
+        assert!(matches!(
+            Replay::from_bytes(&replay.to_bytes()),
+            Err(ReplayError::UnsupportedVersion(version)) if version == REPLAY_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let (replay, states) = record();
+        let mut player = ReplayPlayer::new(Replay::from_bytes(&replay.to_bytes()).unwrap());
+        while !player.is_finished() {
+            player.step();
+        }
+
+        let replayed: Vec<_> = player.states().collect();
+        assert_eq!(replayed.len(), states.len());
+        for ((uuid, replayed), (expected, (id, _))) in replayed
+            .into_iter()
+            .zip(states.iter().zip(&replay.header.players))
+        {
+            assert_eq!(uuid, *id);
+            assert_eq!(replayed.tick, expected.tick);
+            assert_eq!(replayed.is_game_over, expected.is_game_over);
+            assert_eq!(replayed.checksum(), expected.checksum());
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use crate::game::GameState;
+use crate::input::Input;
+use std::collections::VecDeque;
+
+// Most ticks a game is predicted ahead of its last confirmed state
+pub const MAX_PREDICTION_TICKS: usize = 30;
+
+// Predicts a remote game ahead of the last state confirmed by the server, assuming the player
+// makes no inputs. Predicted states are kept in a ring buffer, so a correct prediction is simply
+// dropped once confirmed and a wrong one is rolled back and re-simulated.
+#[derive(Clone, Debug, Default)]
+pub struct Prediction {
+    // `states[i]` is the predicted state `i + 1` ticks after the confirmed state
+    states: VecDeque<GameState>,
+}
+
+impl Prediction {
+    pub fn new() -> Prediction {
+        Prediction::default()
+    }
+
+    pub fn latest(&self) -> Option<&GameState> {
+        self.states.back()
+    }
+
+    // Extends the prediction until it reaches `tick`, the game ends or the buffer is full
+    pub fn predict_to(&mut self, confirmed: &GameState, tick: u64) {
+        while self.states.len() < MAX_PREDICTION_TICKS {
+            let last = self.states.back().unwrap_or(confirmed);
+            if last.tick >= tick || last.is_game_over {
+                break;
+            }
+
+            let mut next = last.clone();
+            next.tick(Input::None);
+            self.states.push_back(next);
+        }
+    }
+
+    // Called after the server confirmed the next tick of the game, with the input it applied
+    pub fn confirm(&mut self, confirmed: &GameState, input: Input) {
+        match self.states.front() {
+            Some(predicted) if input == Input::None && predicted.tick == confirmed.tick => {
+                self.states.pop_front();
+            }
+            _ => self.rollback(confirmed),
+        }
+    }
+
+    // Re-simulates the prediction from `confirmed`, up to the tick it had already reached
+    pub fn rollback(&mut self, confirmed: &GameState) {
+        let target = self.states.back().map(|state| state.tick);
+        self.states.clear();
+        if let Some(target) = target {
+            self.predict_to(confirmed, target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advance(state: &GameState, inputs: &[Input]) -> GameState {
+        let mut state = state.clone();
+        for input in inputs {
+            state.tick(*input);
+        }
+        state
+    }
+
+    #[test]
+    fn predicts_up_to_tick() {
+        let confirmed = GameState::with_seed(1);
+        let mut prediction = Prediction::new();
+        assert!(prediction.latest().is_none());
+
+        prediction.predict_to(&confirmed, confirmed.tick + 5);
+        let latest = prediction.latest().unwrap();
+        assert_eq!(latest.tick, confirmed.tick + 5);
+        assert_eq!(
+            latest.checksum(),
+            advance(&confirmed, &[Input::None; 5]).checksum()
+        );
+
+        // Already there
+        prediction.predict_to(&confirmed, confirmed.tick + 3);
+        assert_eq!(prediction.latest().unwrap().tick, confirmed.tick + 5);
+    }
+
+    #[test]
+    fn stops_when_full_or_over() {
+        let confirmed = GameState::with_seed(1);
+        let mut prediction = Prediction::new();
+        prediction.predict_to(&confirmed, confirmed.tick + 1000);
+        assert_eq!(
+            prediction.latest().unwrap().tick,
+            confirmed.tick + MAX_PREDICTION_TICKS as u64
+        );
+
+        let mut over = confirmed.clone();
+        over.is_game_over = true;
+        let mut prediction = Prediction::new();
+        prediction.predict_to(&over, over.tick + 10);
+        assert!(prediction.latest().is_none());
+    }
+
+    #[test]
+    fn correct_prediction_is_kept() {
+        let mut confirmed = GameState::with_seed(1);
+        let mut prediction = Prediction::new();
+        prediction.predict_to(&confirmed, confirmed.tick + 10);
+        let predicted = prediction.latest().unwrap().checksum();
+
+        confirmed.tick(Input::None);
+        prediction.confirm(&confirmed, Input::None);
+        assert_eq!(prediction.states.len(), 9);
+        assert_eq!(prediction.latest().unwrap().checksum(), predicted);
+    }
+
+    #[test]
+    fn wrong_prediction_is_rolled_back() {
+        let start = GameState::with_seed(1);
+        let mut prediction = Prediction::new();
+        prediction.predict_to(&start, start.tick + 10);
+
+        let confirmed = advance(&start, &[Input::Jump]);
+        prediction.confirm(&confirmed, Input::Jump);
+
+        let mut inputs = vec![Input::Jump];
+        inputs.extend([Input::None; 9]);
+        let expected = advance(&start, &inputs);
+        assert_ne!(
+            expected.checksum(),
+            advance(&start, &[Input::None; 10]).checksum()
+        );
+        let latest = prediction.latest().unwrap();
+        assert_eq!(latest.tick, start.tick + 10);
+        assert_eq!(latest.checksum(), expected.checksum());
+        assert_eq!(prediction.states.len(), 9);
+    }
+
+    #[test]
+    fn skipped_ticks_are_rolled_back() {
+        let start = GameState::with_seed(1);
+        let mut prediction = Prediction::new();
+        prediction.predict_to(&start, start.tick + 10);
+
+        // The confirmed state jumped ahead, e.g. after a snapshot
+        let confirmed = advance(&start, &[Input::None, Input::Duck, Input::Unduck]);
+        prediction.confirm(&confirmed, Input::None);
+        let latest = prediction.latest().unwrap();
+        assert_eq!(latest.tick, start.tick + 10);
+        assert_eq!(prediction.states.front().unwrap().tick, confirmed.tick + 1);
+    }
+
+    #[test]
+    fn rollback_without_prediction() {
+        let confirmed = GameState::with_seed(1);
+        let mut prediction = Prediction::new();
+        prediction.rollback(&confirmed);
+        assert!(prediction.latest().is_none());
+    }
+}
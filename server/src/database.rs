@@ -0,0 +1,87 @@
+use actix::prelude::*;
+use uuid::Uuid;
+
+use crate::ratings::{Placement, Rating, RatingStore};
+use crate::storage::{GameRecord, GameStore, LeaderboardEntry, StorageError};
+
+// Owns the ratings and finished games. Runs on an arbiter of its own so that slow database
+// writes neither block lobbies nor hold the AppState lock.
+pub(crate) struct Database {
+    ratings: RatingStore,
+    games: Box<dyn GameStore>,
+}
+
+impl Database {
+    pub(crate) fn new(ratings: RatingStore, games: Box<dyn GameStore>) -> Database {
+        Database { ratings, games }
+    }
+}
+
+impl Actor for Database {
+    type Context = Context<Self>;
+}
+
+#[derive(Message)]
+#[rtype("()")]
+pub(crate) struct RecordGame {
+    pub(crate) lobby_id: String,
+    pub(crate) placements: Vec<Placement>,
+    pub(crate) game: GameRecord,
+}
+
+#[derive(Message)]
+#[rtype("rusqlite::Result<Vec<Rating>>")]
+pub(crate) struct TopRatings {
+    pub(crate) limit: u32,
+}
+
+#[derive(Message)]
+#[rtype("rusqlite::Result<Option<Rating>>")]
+pub(crate) struct GetRating {
+    pub(crate) player: Uuid,
+}
+
+#[derive(Message)]
+#[rtype("Result<Vec<LeaderboardEntry>, StorageError>")]
+pub(crate) struct GetLeaderboard {
+    pub(crate) since: u64,
+    pub(crate) offset: u32,
+    pub(crate) limit: u32,
+}
+
+impl Handler<RecordGame> for Database {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordGame, _ctx: &mut Self::Context) -> Self::Result {
+        if let Err(e) = self.ratings.record_game(&msg.placements) {
+            eprintln!("Failed to record ratings for lobby {}: {}", msg.lobby_id, e);
+        }
+        if let Err(e) = self.games.record_game(&msg.game) {
+            eprintln!("Failed to record game for lobby {}: {}", msg.lobby_id, e);
+        }
+    }
+}
+
+impl Handler<TopRatings> for Database {
+    type Result = rusqlite::Result<Vec<Rating>>;
+
+    fn handle(&mut self, msg: TopRatings, _ctx: &mut Self::Context) -> Self::Result {
+        self.ratings.top(msg.limit)
+    }
+}
+
+impl Handler<GetRating> for Database {
+    type Result = rusqlite::Result<Option<Rating>>;
+
+    fn handle(&mut self, msg: GetRating, _ctx: &mut Self::Context) -> Self::Result {
+        self.ratings.get(&msg.player)
+    }
+}
+
+impl Handler<GetLeaderboard> for Database {
+    type Result = Result<Vec<LeaderboardEntry>, StorageError>;
+
+    fn handle(&mut self, msg: GetLeaderboard, _ctx: &mut Self::Context) -> Self::Result {
+        self.games.leaderboard(msg.since, msg.offset, msg.limit)
+    }
+}
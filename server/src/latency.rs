@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Number of round trips latency and jitter are averaged over
+const LATENCY_SAMPLES: usize = 10;
+// Input delay used until a player's latency has been measured
+pub(crate) const DEFAULT_INPUT_DELAY_TICKS: u64 = 10;
+const MAX_INPUT_DELAY_TICKS: u64 = 40;
+
+// Round trip times of a player's connection, measured with pings
+#[derive(Debug, Default)]
+pub(crate) struct Latency {
+    next_ping_id: u64,
+    // Only the latest ping is waited for, so lost pings are simply replaced
+    pending: Option<(u64, Instant)>,
+    samples: VecDeque<Duration>,
+}
+
+impl Latency {
+    // Returns the id to send with the ping
+    pub(crate) fn start_ping(&mut self) -> u64 {
+        let id = self.next_ping_id;
+        self.next_ping_id += 1;
+        self.pending = Some((id, Instant::now()));
+        id
+    }
+
+    // Returns whether the pong answered the pending ping
+    pub(crate) fn finish_ping(&mut self, id: u64) -> bool {
+        match self.pending {
+            Some((pending_id, sent)) if pending_id == id => {
+                self.pending = None;
+                self.samples.push_back(sent.elapsed());
+                if self.samples.len() > LATENCY_SAMPLES {
+                    self.samples.pop_front();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn rtt(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+
+    // Mean deviation of the round trip times from their average
+    pub(crate) fn jitter(&self) -> Duration {
+        let rtt = match self.rtt() {
+            Some(rtt) => rtt,
+            None => return Duration::ZERO,
+        };
+        let deviation: Duration = self.samples.iter().map(|sample| sample.abs_diff(rtt)).sum();
+        deviation / self.samples.len() as u32
+    }
+
+    // How many ticks ahead of the server the player has to run for their inputs to arrive before
+    // the server simulates the tick they were made on. A server update takes half a round trip to
+    // reach the player, and their input another half to come back.
+    pub(crate) fn input_delay(&self, tick_interval: Duration) -> u64 {
+        let rtt = match self.rtt() {
+            Some(rtt) => rtt,
+            None => return DEFAULT_INPUT_DELAY_TICKS,
+        };
+        let margin = rtt + 2 * self.jitter();
+        let ticks = margin.as_micros().div_ceil(tick_interval.as_micros()) as u64;
+        (ticks + 1).min(MAX_INPUT_DELAY_TICKS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_millis(50);
+
+    fn latency(samples_ms: &[u64]) -> Latency {
+        Latency {
+            samples: samples_ms
+                .iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect(),
+            ..Latency::default()
+        }
+    }
+
+    #[test]
+    fn unmeasured() {
+        let latency = Latency::default();
+        assert_eq!(latency.rtt(), None);
+        assert_eq!(latency.jitter(), Duration::ZERO);
+        assert_eq!(latency.input_delay(TICK), DEFAULT_INPUT_DELAY_TICKS);
+    }
+
+    #[test]
+    fn steady_connection() {
+        let latency = latency(&[100, 100, 100]);
+        assert_eq!(latency.rtt(), Some(Duration::from_millis(100)));
+        assert_eq!(latency.jitter(), Duration::ZERO);
+        // Two ticks for the round trip and one to spare
+        assert_eq!(latency.input_delay(TICK), 3);
+    }
+
+    #[test]
+    fn jitter_adds_margin() {
+        let latency = latency(&[80, 120, 80, 120]);
+        assert_eq!(latency.rtt(), Some(Duration::from_millis(100)));
+        assert_eq!(latency.jitter(), Duration::from_millis(20));
+        assert_eq!(latency.input_delay(TICK), 4);
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        assert_eq!(latency(&[0]).input_delay(TICK), 1);
+        assert_eq!(latency(&[10_000]).input_delay(TICK), MAX_INPUT_DELAY_TICKS);
+    }
+
+    #[test]
+    fn pings() {
+        let mut latency = Latency::default();
+        let first = latency.start_ping();
+        let second = latency.start_ping();
+        assert_ne!(first, second);
+
+        // Only the latest ping counts
+        assert!(!latency.finish_ping(first));
+        assert!(latency.finish_ping(second));
+        assert!(!latency.finish_ping(second));
+        assert!(latency.rtt().is_some());
+
+        for _ in 0..LATENCY_SAMPLES * 2 {
+            let id = latency.start_ping();
+            latency.finish_ping(id);
+        }
+        assert_eq!(latency.samples.len(), LATENCY_SAMPLES);
+    }
+}
@@ -1,20 +1,51 @@
 use actix::prelude::*;
+use actix::WeakAddr;
+use std::cmp::Reverse;
 use std::collections::{HashMap, VecDeque};
+use std::fs;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+use crate::database::RecordGame;
+use crate::latency::{Latency, DEFAULT_INPUT_DELAY_TICKS};
+use crate::matchmaker::{LobbyStatus, Matchmaker};
+use crate::ratings::Placement;
+use crate::scheduler::TickScheduler;
 use crate::server::ClientConnection;
-use game::game::GameState;
+use crate::storage::{unix_time, GameRecord, GameResult};
+use crate::AppState;
+use game::game::{GameObstacle, GameState};
 use game::input::Input;
 use game::messages::S2CMessage::{
-    InvalidMessage, LobbyJoinEvent, LobbyJoinFailureResponse, LobbyJoinSuccess,
-    LobbyStateChangeEvent,
+    AttackEvent, GameStartEvent, InvalidMessage, LobbyJoinEvent, LobbyJoinFailureResponse,
+    LobbyJoinSuccess, LobbyStateChangeEvent,
 };
-use game::messages::{C2SMessage, LobbyState, PlayerInfo, PlayerState, S2CMessage};
+use game::messages::{
+    C2SMessage, LobbyConfig, LobbyState, PlayerInfo, PlayerState, S2CMessage, Standing,
+    TargetingStrategy, CHECKSUM_INTERVAL_TICKS,
+};
+use game::replay::{Replay, ReplayTick};
+use game::rng::Rng;
 
 pub(crate) type LobbyId = String;
 
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+// Tick counts are given at the physics rate and scaled down for lobbies that tick slower
+const LEADERBOARD_INTERVAL_TICKS: u64 = 10;
+// Attacks land a while after they are sent so that clients usually learn about them before the
+// target reaches that tick. A target running further ahead replays its game with the attack.
+const ATTACK_DELAY_TICKS: u64 = 40;
+const MAX_ATTACK_OBSTACLES: u32 = 4;
+const RANDOM_RETARGET_TICKS: u64 = 100;
+// A player that disconnects mid-game forfeits if they do not come back within this many ticks
+const DISCONNECT_GRACE_TICKS: u64 = 200;
+// How often lobbies check whether they have been idle for long enough to shut down
+const LIFECYCLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// Snapshots hold every game state, so each connection only gets one this often
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Message)]
 #[rtype("()")]
 pub(crate) struct ServerMessage {
@@ -29,31 +60,106 @@ pub(crate) struct PlayerMessage {
     pub(crate) recipient: Addr<ClientConnection>,
 }
 
-#[derive(Debug)]
+#[derive(Message)]
+#[rtype("()")]
+pub(crate) struct PlayerDisconnected {
+    pub(crate) client_id: Uuid,
+    pub(crate) connection: Addr<ClientConnection>,
+}
+
 pub(crate) struct LobbyActor {
+    id: LobbyId,
+    // Set once the actor has started
+    address: Option<WeakAddr<LobbyActor>>,
+    lobbies: Arc<Mutex<AppState>>,
+    idle_timeout: Duration,
+    last_activity: Instant,
+    config: LobbyConfig,
+    // Set while enough players have joined and the lobby is waiting for more
+    fill_deadline: Option<Instant>,
+    countdown_end: Option<Instant>,
+    countdown_remaining: u64,
     state: LobbyState,
     players: HashMap<Uuid, Player>,
+    spectators: HashMap<Uuid, Spectator>,
+    // When each connection was last sent a snapshot
+    last_snapshots: HashMap<Uuid, Instant>,
+    scheduler: TickScheduler,
     current_tick: AtomicU64,
+    // Ticks to wait after the game starts, giving every client a head start
     server_delay: u64,
+    // Only private lobbies have a host
+    host: Option<Uuid>,
+    // Set for public lobbies created by the matchmaker
+    matchmaker: Option<Addr<Matchmaker>>,
+    // Player count and whether the lobby was open when it last reported to the matchmaker
+    reported_status: Option<(u32, bool)>,
+    // Recorded while a game is in progress and saved once it ends
+    replay: Option<Replay>,
+    replay_id: Uuid,
+    rng: Rng,
 }
 
 impl LobbyActor {
-    pub(crate) fn new() -> LobbyActor {
+    pub(crate) fn new(
+        id: LobbyId,
+        lobbies: Arc<Mutex<AppState>>,
+        idle_timeout: Duration,
+        config: LobbyConfig,
+    ) -> LobbyActor {
         LobbyActor {
+            id,
+            address: None,
+            lobbies,
+            idle_timeout,
+            last_activity: Instant::now(),
+            config,
+            fill_deadline: None,
+            countdown_end: None,
+            countdown_remaining: 0,
             state: LobbyState::Waiting,
             players: HashMap::new(),
+            spectators: HashMap::new(),
+            last_snapshots: HashMap::new(),
+            scheduler: TickScheduler::new(config.game.tick_rate),
             current_tick: AtomicU64::new(0),
-            server_delay: 10,
+            server_delay: DEFAULT_INPUT_DELAY_TICKS,
+            host: None,
+            matchmaker: None,
+            reported_status: None,
+            replay: None,
+            replay_id: Uuid::new_v4(),
+            rng: Rng::new(0),
         }
     }
+
+    pub(crate) fn with_matchmaker(mut self, matchmaker: Addr<Matchmaker>) -> LobbyActor {
+        self.matchmaker = Some(matchmaker);
+        self
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct Player {
     info: PlayerInfo,
     game_state: GameState,
-    connection: Addr<ClientConnection>,
+    connection: Option<Addr<ClientConnection>>,
+    disconnected_tick: Option<u64>,
+    session_token: Uuid,
+    // Identifies the player across games, which ratings and results are kept under
+    player_token: Uuid,
     future_inputs: VecDeque<(u64, Input)>,
+    attack_points_sent: u32,
+    strategy: TargetingStrategy,
+    target: Option<Uuid>,
+    last_attacker: Option<Uuid>,
+    badges: u32,
+    latency: Latency,
+}
+
+// Receives every broadcast but has no game state of its own
+pub(crate) struct Spectator {
+    connection: Addr<ClientConnection>,
 }
 
 impl Player {
@@ -72,13 +178,15 @@ impl Player {
             }
         }
 
-        return Input::None;
+        Input::None
     }
 
     fn send_message(&self, msg: S2CMessage) {
-        self.connection.do_send(ServerMessage {
-            server_message: msg,
-        });
+        if let Some(connection) = &self.connection {
+            connection.do_send(ServerMessage {
+                server_message: msg,
+            });
+        }
     }
 }
 
@@ -86,23 +194,117 @@ impl Actor for LobbyActor {
     type Context = Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        ctx.run_interval(Duration::from_millis(50), |lobby, _ctx| {
-            lobby.on_tick();
+        self.address = Some(ctx.address().downgrade());
+        self.schedule_tick(ctx);
+        ctx.run_interval(PING_INTERVAL, |lobby, _ctx| {
+            lobby.send_pings();
+        });
+        ctx.run_interval(LIFECYCLE_CHECK_INTERVAL, |lobby, ctx| {
+            if lobby.last_activity.elapsed() >= lobby.idle_timeout {
+                ctx.stop();
+            }
         });
     }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> Running {
+        // Unregister before stopping so that the lobby id can be reused right away
+        self.unregister();
+
+        self.state = LobbyState::Ended;
+        self.report_status();
+        self.broadcast(S2CMessage::LobbyClosedEvent);
+        Running::Stop
+    }
 }
 
 impl LobbyActor {
+    // Frees the lobby id up for a new lobby. A newer lobby may already have taken the id, in
+    // which case it is left alone.
+    fn unregister(&self) {
+        let address = match self.address.as_ref().and_then(|address| address.upgrade()) {
+            Some(address) => address,
+            None => return,
+        };
+        let lobbies = &mut self.lobbies.lock().unwrap().lobbies;
+        if lobbies.get(&self.id) == Some(&address) {
+            lobbies.remove(&self.id);
+        }
+    }
+
     fn broadcast(&mut self, message: S2CMessage) {
-        for (_uuid, player) in &self.players {
+        for player in self.players.values() {
             player.send_message(message.clone());
         }
+        for spectator in self.spectators.values() {
+            spectator.connection.do_send(ServerMessage {
+                server_message: message.clone(),
+            });
+        }
+    }
+
+    fn schedule_tick(&mut self, ctx: &mut Context<Self>) {
+        let delay = self
+            .scheduler
+            .next_tick_at()
+            .saturating_duration_since(Instant::now());
+        ctx.run_later(delay, |lobby, ctx| {
+            lobby.run_due_ticks();
+            lobby.schedule_tick(ctx);
+        });
+    }
+
+    fn run_due_ticks(&mut self) {
+        let due = self.scheduler.due_ticks(Instant::now());
+        let interval = self.scheduler.interval();
+
+        let mut overruns = 0;
+        let mut slowest_tick = Duration::ZERO;
+        for _ in 0..due.run {
+            let started = Instant::now();
+            self.on_tick();
+
+            let elapsed = started.elapsed();
+            slowest_tick = slowest_tick.max(elapsed);
+            if elapsed > interval {
+                overruns += 1;
+            }
+        }
+
+        if due.run > 1 || due.skipped > 0 || overruns > 0 {
+            let metrics = &mut self.lobbies.lock().unwrap().tick_metrics;
+            metrics.record(due, overruns, slowest_tick);
+        }
+    }
+
+    // Players of the current game in the order the replay numbers them, which binary
+    // connections also use to refer to them
+    fn player_order(&self) -> Vec<Uuid> {
+        self.replay.as_ref().map_or(vec![], |replay| {
+            replay
+                .header
+                .players
+                .iter()
+                .map(|(uuid, _)| *uuid)
+                .collect()
+        })
+    }
+
+    fn send_pings(&mut self) {
+        for player in self.players.values_mut() {
+            if player.connection.is_some() {
+                let id = player.latency.start_ping();
+                player.send_message(S2CMessage::PingEvent { id });
+            }
+        }
     }
 
     fn on_tick(&mut self) {
         if self.state != LobbyState::InPlay {
+            self.update_start_timers();
             return;
         }
+        // A running game keeps the lobby alive even if nobody is sending inputs
+        self.last_activity = Instant::now();
 
         if self.server_delay > 0 {
             self.server_delay -= 1;
@@ -118,22 +320,639 @@ impl LobbyActor {
             player.game_state.tick(input);
 
             if input != Input::None {
-                player_info.push((uuid.clone(), input));
+                player_info.push((*uuid, input));
             }
         }
 
+        let mut checksums = vec![];
+        if current_tick.is_multiple_of(CHECKSUM_INTERVAL_TICKS) {
+            checksums = self
+                .players
+                .iter()
+                .map(|(uuid, player)| (*uuid, player.game_state.checksum()))
+                .collect();
+        }
+
         self.broadcast(S2CMessage::GameTickEvent {
             tick: current_tick,
-            players: player_info,
+            players: player_info.clone(),
+            checksums,
+        });
+
+        let forfeited = self.handle_forfeits(current_tick);
+        self.handle_eliminations(current_tick);
+
+        let mut attacks = vec![];
+        if self.state == LobbyState::InPlay {
+            let alive = self.alive_players();
+
+            self.resolve_targets(&alive, current_tick);
+            attacks = self.resolve_attacks(&alive, current_tick);
+
+            if current_tick.is_multiple_of(self.ticks(LEADERBOARD_INTERVAL_TICKS)) {
+                self.broadcast_leaderboard(current_tick);
+            }
+        }
+
+        if let Some(replay) = &mut self.replay {
+            let index = |uuid: &Uuid| replay.player_index(uuid).unwrap();
+            let tick = ReplayTick {
+                tick: current_tick,
+                inputs: player_info
+                    .iter()
+                    .map(|(uuid, input)| (index(uuid), *input))
+                    .collect(),
+                forfeits: forfeited.iter().map(index).collect(),
+                attacks: attacks
+                    .into_iter()
+                    .map(|(target, tick, obstacles)| (index(&target), tick, obstacles))
+                    .collect(),
+            };
+            replay.record_tick(tick);
+        }
+
+        if self.state == LobbyState::Ended {
+            self.save_replay();
+        }
+    }
+
+    fn save_replay(&mut self) {
+        let replay = match self.replay.take() {
+            Some(replay) => replay,
+            None => return,
+        };
+
+        let replay_dir = self.lobbies.lock().unwrap().replay_dir.clone();
+        let path = replay_dir.join(format!("{}.replay", self.replay_id));
+        let result =
+            fs::create_dir_all(&replay_dir).and_then(|_| fs::write(&path, replay.to_bytes()));
+        if let Err(e) = result {
+            eprintln!("Failed to save replay {:?}: {}", path, e);
+        }
+    }
+
+    // Moves the lobby between waiting and counting down as players join, leave and ready up
+    fn check_start_conditions(&mut self) {
+        let count = self.players.len() as u32;
+        let all_ready = self.players.values().all(|player| player.info.is_ready);
+        match self.state {
+            LobbyState::Waiting if count >= self.config.min_players && all_ready => {
+                self.start_countdown()
+            }
+            LobbyState::Waiting if self.config.is_private => self.fill_deadline = None,
+            LobbyState::Waiting if count >= self.config.max_players => self.start_countdown(),
+            LobbyState::Waiting if count >= self.config.min_players => {
+                let fill_timeout = Duration::from_secs(self.config.fill_timeout_secs);
+                self.fill_deadline
+                    .get_or_insert_with(|| Instant::now() + fill_timeout);
+            }
+            LobbyState::Waiting => self.fill_deadline = None,
+            LobbyState::Countdown if count < self.config.min_players => {
+                self.fill_deadline = None;
+                self.countdown_end = None;
+                self.state = LobbyState::Waiting;
+                self.broadcast(LobbyStateChangeEvent {
+                    new_state: LobbyState::Waiting,
+                });
+            }
+            _ => {}
+        }
+
+        self.report_status();
+    }
+
+    // Only changes are reported, as the matchmaker also counts the players it has sent here but
+    // that have not joined yet
+    fn report_status(&mut self) {
+        let players = self.players.len() as u32;
+        let is_open = matches!(self.state, LobbyState::Waiting | LobbyState::Countdown)
+            && players < self.config.max_players;
+        if self.reported_status == Some((players, is_open)) {
+            return;
+        }
+
+        if let Some(matchmaker) = &self.matchmaker {
+            self.reported_status = Some((players, is_open));
+            matchmaker.do_send(LobbyStatus {
+                id: self.id.clone(),
+                players,
+                is_open,
+            });
+        }
+    }
+
+    fn start_countdown(&mut self) {
+        let countdown = Duration::from_secs(self.config.countdown_secs);
+        self.fill_deadline = None;
+        self.countdown_end = Some(Instant::now() + countdown);
+        self.countdown_remaining = self.config.countdown_secs;
+        self.state = LobbyState::Countdown;
+        self.broadcast(LobbyStateChangeEvent {
+            new_state: LobbyState::Countdown,
+        });
+        self.broadcast(S2CMessage::CountdownEvent {
+            seconds_remaining: self.countdown_remaining,
+        });
+    }
+
+    fn update_start_timers(&mut self) {
+        let now = Instant::now();
+        // A lobby that is about to start or has connected players waiting in it is not idle
+        let has_waiting_players = matches!(self.state, LobbyState::Waiting | LobbyState::Countdown)
+            && self
+                .players
+                .values()
+                .any(|player| player.connection.is_some());
+        if has_waiting_players || self.fill_deadline.is_some() || self.countdown_end.is_some() {
+            self.last_activity = now;
+        }
+
+        match self.state {
+            LobbyState::Waiting if self.fill_deadline.is_some_and(|deadline| now >= deadline) => {
+                self.start_countdown();
+            }
+            LobbyState::Countdown => {
+                let remaining = match self.countdown_end {
+                    Some(end) => end.saturating_duration_since(now),
+                    None => return,
+                };
+                if remaining.is_zero() {
+                    self.countdown_end = None;
+                    self.do_game_start();
+                    return;
+                }
+
+                // Round up so that the countdown reads N, ..., 1 before the game starts
+                let seconds_remaining = remaining.as_millis().div_ceil(1000) as u64;
+                if seconds_remaining != self.countdown_remaining {
+                    self.countdown_remaining = seconds_remaining;
+                    self.broadcast(S2CMessage::CountdownEvent { seconds_remaining });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ticks(&self, physics_ticks: u64) -> u64 {
+        physics_ticks.div_ceil(self.config.game.steps_per_tick())
+    }
+
+    fn handle_forfeits(&mut self, current_tick: u64) -> Vec<Uuid> {
+        let grace_ticks = self.ticks(DISCONNECT_GRACE_TICKS);
+        let mut forfeited = vec![];
+        for (uuid, player) in &mut self.players {
+            match player.disconnected_tick {
+                Some(tick) if current_tick >= tick + grace_ticks => {
+                    player.disconnected_tick = None;
+                    player.game_state.is_game_over = true;
+                    forfeited.push(*uuid);
+                }
+                _ => {}
+            }
+        }
+
+        for uuid in &forfeited {
+            self.broadcast(S2CMessage::LobbyLeaveEvent { player: *uuid });
+        }
+        forfeited
+    }
+
+    fn alive_players(&self) -> Vec<Uuid> {
+        let mut alive: Vec<Uuid> = self
+            .players
+            .iter()
+            .filter(|(_, player)| player.info.state == PlayerState::Playing)
+            .map(|(uuid, _)| *uuid)
+            .collect();
+        alive.sort();
+        alive
+    }
+
+    // Players knocked out on the same tick are placed by score. The last player to attack someone
+    // gets a badge when they are knocked out.
+    fn handle_eliminations(&mut self, current_tick: u64) {
+        let alive = self.alive_players();
+        let mut eliminated: Vec<Uuid> = alive
+            .iter()
+            .filter(|uuid| self.players[*uuid].game_state.is_game_over)
+            .copied()
+            .collect();
+        if eliminated.is_empty() {
+            return;
+        }
+
+        eliminated.sort_by_key(|uuid| (Reverse(self.players[uuid].game_state.score), *uuid));
+
+        let first_placement = (alive.len() - eliminated.len() + 1) as u32;
+        for (idx, uuid) in eliminated.iter().enumerate() {
+            let placement = first_placement + idx as u32;
+            let player = self.players.get_mut(uuid).unwrap();
+            // Eliminated players keep watching the rest of the game
+            player.info.state = PlayerState::Spectating;
+            player.info.placement = Some(placement);
+            let eliminated_by = player.last_attacker;
+
+            self.broadcast(S2CMessage::PlayerEliminatedEvent {
+                player: *uuid,
+                tick: current_tick,
+                placement,
+                eliminated_by,
+            });
+
+            if let Some(attacker) = eliminated_by.and_then(|a| self.players.get_mut(&a)) {
+                attacker.badges += 1;
+                let badges = attacker.badges;
+                self.broadcast(S2CMessage::BadgeEvent {
+                    player: eliminated_by.unwrap(),
+                    badges,
+                });
+            }
+        }
+
+        let remaining = self.alive_players();
+        if remaining.len() <= 1 {
+            if let Some(winner) = remaining.first() {
+                self.players.get_mut(winner).unwrap().info.placement = Some(1);
+            }
+            self.do_game_end();
+        }
+    }
+
+    fn do_spectate(&mut self, client_id: Uuid, recipient: Addr<ClientConnection>) {
+        if self.players.contains_key(&client_id) {
+            recipient.do_send(ServerMessage {
+                server_message: InvalidMessage {
+                    error: "Already playing in this lobby!".to_string(),
+                },
+            });
+            return;
+        }
+
+        let players = self.players.values().map(|p| p.info.clone()).collect();
+        let game_states = self
+            .players
+            .iter()
+            .map(|(uuid, p)| (*uuid, p.game_state.clone()))
+            .collect();
+
+        recipient.do_send(ServerMessage {
+            server_message: S2CMessage::SpectateSuccess {
+                spectator_id: client_id,
+                lobby_state: self.state.clone(),
+                config: self.config,
+                players,
+                game_states,
+                player_order: self.player_order(),
+            },
+        });
+        self.spectators.insert(
+            client_id,
+            Spectator {
+                connection: recipient,
+            },
+        );
+    }
+
+    // Re-binds an existing player to a new connection and sends it everything needed to rebuild
+    // the game states
+    fn do_resume(&mut self, session_token: Uuid, recipient: Addr<ClientConnection>) {
+        let player = self
+            .players
+            .values_mut()
+            .find(|player| player.session_token == session_token);
+
+        let player = match player {
+            Some(player) => player,
+            None => {
+                recipient.do_send(ServerMessage {
+                    server_message: LobbyJoinFailureResponse {
+                        reason: "Unknown session".to_string(),
+                    },
+                });
+                return;
+            }
+        };
+
+        player.connection = Some(recipient);
+        player.disconnected_tick = None;
+        player.future_inputs.clear();
+        let player_id = player.info.id;
+
+        let players = self.players.values().map(|p| p.info.clone()).collect();
+        let game_states = self
+            .players
+            .iter()
+            .map(|(uuid, p)| (*uuid, p.game_state.clone()))
+            .collect();
+
+        self.players[&player_id].send_message(S2CMessage::ResumeSuccess {
+            player_id,
+            session_token,
+            lobby_state: self.state.clone(),
+            config: self.config,
+            host: self.host,
+            players,
+            game_states,
+            player_order: self.player_order(),
+        });
+    }
+
+    fn remove_player(&mut self, uuid: Uuid) {
+        self.players.remove(&uuid);
+        self.broadcast(S2CMessage::LobbyLeaveEvent { player: uuid });
+
+        if self.host == Some(uuid) {
+            self.host = self.players.keys().min().copied();
+            if let Some(host) = self.host {
+                self.broadcast(S2CMessage::HostChangeEvent { host });
+            }
+        }
+
+        self.check_start_conditions();
+    }
+
+    fn handle_host_message(&mut self, message: C2SMessage) -> Result<(), String> {
+        if !matches!(self.state, LobbyState::Waiting | LobbyState::Countdown) {
+            return Err("The game has already started!".to_string());
+        }
+
+        match message {
+            C2SMessage::StartGame => {
+                if self.state == LobbyState::Countdown {
+                    return Err("The game is already starting!".to_string());
+                }
+                if (self.players.len() as u32) < self.config.min_players {
+                    return Err("Not enough players!".to_string());
+                }
+                self.start_countdown();
+            }
+            C2SMessage::KickPlayer { player } => {
+                if Some(player) == self.host {
+                    return Err("The host cannot be kicked!".to_string());
+                }
+                let kicked = match self.players.get(&player) {
+                    Some(kicked) => kicked,
+                    None => return Err("No such player!".to_string()),
+                };
+                kicked.send_message(S2CMessage::KickedEvent);
+                self.remove_player(player);
+            }
+            C2SMessage::ChangeLobbySettings { config } => {
+                config.validate()?;
+                if (self.players.len() as u32) > config.max_players {
+                    return Err("Too many players for that size!".to_string());
+                }
+
+                self.config = LobbyConfig {
+                    is_private: self.config.is_private,
+                    ..config
+                };
+                self.broadcast(S2CMessage::LobbySettingsChangeEvent {
+                    config: self.config,
+                });
+                self.check_start_conditions();
+            }
+            C2SMessage::TransferHost { player } => {
+                if !self.players.contains_key(&player) {
+                    return Err("No such player!".to_string());
+                }
+                self.host = Some(player);
+                self.broadcast(S2CMessage::HostChangeEvent { host: player });
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn do_game_end(&mut self) {
+        let mut standings: Vec<Standing> = self
+            .players
+            .values()
+            .map(|player| Standing {
+                player: player.info.id,
+                username: player.info.username.clone(),
+                placement: player.info.placement.unwrap_or(1),
+                score: player.game_state.score,
+                badges: player.badges,
+            })
+            .collect();
+        standings.sort_by_key(|standing| standing.placement);
+
+        let placements: Vec<Placement> = standings
+            .iter()
+            .map(|standing| Placement {
+                player: self.players[&standing.player].player_token,
+                name: standing.username.clone(),
+                placement: standing.placement,
+            })
+            .collect();
+        let game = GameRecord {
+            lobby_id: self.id.clone(),
+            finished_at: unix_time(),
+            duration_ticks: self.current_tick.load(Ordering::Relaxed),
+            results: standings
+                .iter()
+                .map(|standing| GameResult {
+                    player: self.players[&standing.player].player_token,
+                    username: standing.username.clone(),
+                    placement: standing.placement,
+                    score: standing.score,
+                    badges: standing.badges,
+                })
+                .collect(),
+        };
+
+        let database = self.lobbies.lock().unwrap().database.clone();
+        database.do_send(RecordGame {
+            lobby_id: self.id.clone(),
+            placements,
+            game,
+        });
+
+        self.state = LobbyState::Ended;
+        self.last_activity = Instant::now();
+        // The lobby only lingers so that players can see the results, so a new game can take
+        // its id right away
+        self.unregister();
+        self.broadcast(S2CMessage::GameOverEvent {
+            standings,
+            replay_id: self.replay.as_ref().map(|_| self.replay_id),
+        });
+        self.broadcast(LobbyStateChangeEvent {
+            new_state: LobbyState::Ended,
         });
     }
 
+    fn pick_target(&mut self, uuid: Uuid, alive: &[Uuid], current_tick: u64) -> Option<Uuid> {
+        let player = &self.players[&uuid];
+        let opponents: Vec<Uuid> = alive.iter().filter(|o| **o != uuid).copied().collect();
+        if opponents.is_empty() {
+            return None;
+        }
+
+        let current = player.target.filter(|target| opponents.contains(target));
+        let max_by = |metric: &dyn Fn(&Player) -> usize| {
+            let best = opponents
+                .iter()
+                .map(|o| metric(&self.players[o]))
+                .max()
+                .unwrap();
+            opponents
+                .iter()
+                .filter(|o| metric(&self.players[*o]) == best)
+                .copied()
+                .collect::<Vec<Uuid>>()
+        };
+
+        let candidates = match player.strategy {
+            TargetingStrategy::Random => {
+                if current.is_some()
+                    && !current_tick.is_multiple_of(self.ticks(RANDOM_RETARGET_TICKS))
+                {
+                    return current;
+                }
+                opponents.clone()
+            }
+            TargetingStrategy::Attackers => {
+                let attackers: Vec<Uuid> = opponents
+                    .iter()
+                    .filter(|o| self.players[*o].target == Some(uuid))
+                    .copied()
+                    .collect();
+                if attackers.is_empty() {
+                    if current.is_some() {
+                        return current;
+                    }
+                    opponents.clone()
+                } else {
+                    attackers
+                }
+            }
+            TargetingStrategy::KOs => max_by(&|p| p.game_state.incoming_obstacles.len()),
+            TargetingStrategy::Badges => max_by(&|p| p.badges as usize),
+        };
+
+        if let Some(target) = current {
+            if candidates.contains(&target) {
+                return Some(target);
+            }
+        }
+        Some(candidates[self.rng.range(0, candidates.len() as u32) as usize])
+    }
+
+    fn resolve_targets(&mut self, alive: &[Uuid], current_tick: u64) {
+        for uuid in alive {
+            let target = self.pick_target(*uuid, alive, current_tick);
+            let player = self.players.get_mut(uuid).unwrap();
+            if player.target != target {
+                player.target = target;
+                let strategy = player.strategy;
+                self.broadcast(S2CMessage::TargetChangeEvent {
+                    player: *uuid,
+                    target,
+                    strategy,
+                });
+            }
+        }
+    }
+
+    // Returns the attacks that were sent as (target, tick they land on, obstacles)
+    fn resolve_attacks(
+        &mut self,
+        alive: &[Uuid],
+        current_tick: u64,
+    ) -> Vec<(Uuid, u64, Vec<GameObstacle>)> {
+        let mut attacks = vec![];
+        for uuid in alive {
+            let player = self.players.get_mut(uuid).unwrap();
+            let pending = player
+                .game_state
+                .attack_points
+                .saturating_sub(player.attack_points_sent);
+            let target = match player.target {
+                Some(target) if pending > 0 => target,
+                _ => continue,
+            };
+
+            let count = pending.min(MAX_ATTACK_OBSTACLES);
+            player.attack_points_sent += count;
+
+            let obstacles: Vec<GameObstacle> = (0..count)
+                .map(|_| GameObstacle::random(&mut self.rng))
+                .collect();
+            attacks.push((*uuid, target, obstacles));
+        }
+
+        let apply_tick = current_tick + self.ticks(ATTACK_DELAY_TICKS);
+        let mut sent = vec![];
+        for (attacker, target, obstacles) in attacks {
+            let target_player = self.players.get_mut(&target).unwrap();
+            target_player
+                .game_state
+                .schedule_attack(apply_tick, &obstacles);
+            target_player.last_attacker = Some(attacker);
+
+            sent.push((target, apply_tick, obstacles.clone()));
+            self.broadcast(AttackEvent {
+                attacker,
+                target,
+                tick: apply_tick,
+                obstacles,
+            });
+        }
+        sent
+    }
+
+    fn broadcast_leaderboard(&mut self, tick: u64) {
+        let mut scores: Vec<(Uuid, u64)> = self
+            .players
+            .iter()
+            .map(|(uuid, player)| (*uuid, player.game_state.score))
+            .collect();
+        scores.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        self.broadcast(S2CMessage::LeaderboardEvent { tick, scores });
+    }
+
     fn do_game_start(&mut self) {
-        if !matches!(self.state, LobbyState::Waiting) {
+        if !matches!(self.state, LobbyState::Countdown) {
             return;
         }
 
+        let seed = self.config.seed.unwrap_or_else(rand::random::<u64>);
+        self.rng = Rng::new(!seed);
+        for player in self.players.values_mut() {
+            player.game_state = GameState::with_config(seed, self.config.game);
+            player.attack_points_sent = 0;
+        }
+
+        let mut players: Vec<(Uuid, String)> = self
+            .players
+            .values()
+            .map(|player| (player.info.id, player.info.username.clone()))
+            .collect();
+        players.sort();
+        self.replay = Some(Replay::new(seed, self.config.game, players));
+
+        // Ticks are counted from the start of the game, which may have changed the tick rate
+        self.scheduler.restart(self.config.game.tick_rate);
+        let interval = self.scheduler.interval();
+
+        // Wait long enough for the slowest player's inputs to arrive in time
+        self.server_delay = self
+            .players
+            .values()
+            .map(|player| player.latency.input_delay(interval))
+            .max()
+            .unwrap_or(DEFAULT_INPUT_DELAY_TICKS);
+
         self.state = LobbyState::InPlay;
+        self.report_status();
+        self.broadcast(GameStartEvent {
+            seed,
+            config: self.config.game,
+            player_order: self.player_order(),
+        });
         self.broadcast(LobbyStateChangeEvent {
             new_state: LobbyState::InPlay,
         });
@@ -144,9 +963,20 @@ impl Handler<PlayerMessage> for LobbyActor {
     type Result = ();
 
     fn handle(&mut self, msg: PlayerMessage, _ctx: &mut Self::Context) -> Self::Result {
-        if let C2SMessage::LobbyJoinRequest { lobby_id, name } = msg.client_message {
+        // Ended lobbies only linger so that players can see the results
+        if self.state != LobbyState::Ended {
+            self.last_activity = Instant::now();
+        }
+
+        if let C2SMessage::LobbyJoinRequest {
+            lobby_id,
+            name,
+            player_token,
+            ..
+        } = msg.client_message
+        {
             dbg!("lobby_id={}: player '{}' joined", lobby_id, &name);
-            if !matches!(self.state, LobbyState::Waiting) {
+            if !matches!(self.state, LobbyState::Waiting | LobbyState::Countdown) {
                 msg.recipient.do_send(ServerMessage {
                     server_message: LobbyJoinFailureResponse {
                         reason: "Lobby already started!".to_string(),
@@ -155,8 +985,49 @@ impl Handler<PlayerMessage> for LobbyActor {
                 return;
             }
 
+            if self.players.len() as u32 >= self.config.max_players {
+                msg.recipient.do_send(ServerMessage {
+                    server_message: LobbyJoinFailureResponse {
+                        reason: "Lobby is full!".to_string(),
+                    },
+                });
+                return;
+            }
+
+            // One player joining twice would have the game count twice towards their rating
+            let player_token = player_token.unwrap_or_else(Uuid::new_v4);
+            if self
+                .players
+                .values()
+                .any(|player| player.player_token == player_token)
+            {
+                msg.recipient.do_send(ServerMessage {
+                    server_message: LobbyJoinFailureResponse {
+                        reason: "Already playing in this lobby!".to_string(),
+                    },
+                });
+                return;
+            }
+
+            // Names are all other players see, so they are kept unique within a game
+            if self
+                .players
+                .values()
+                .any(|player| player.info.username == name)
+            {
+                msg.recipient.do_send(ServerMessage {
+                    server_message: LobbyJoinFailureResponse {
+                        reason: "Name already taken!".to_string(),
+                    },
+                });
+                return;
+            }
+
+            // Spectators can still take part in games that have not started yet
+            self.spectators.remove(&msg.client_id);
+
             let mut player_infos = vec![];
-            for (_uuid, player) in &self.players {
+            for player in self.players.values() {
                 player_infos.push(player.info.clone());
             }
 
@@ -167,16 +1038,37 @@ impl Handler<PlayerMessage> for LobbyActor {
                         username: name,
                         id: msg.client_id,
                         state: PlayerState::Playing,
+                        placement: None,
+                        is_ready: false,
                     },
                     future_inputs: VecDeque::new(),
-                    connection: msg.recipient.clone(),
+                    attack_points_sent: 0,
+                    strategy: TargetingStrategy::default(),
+                    target: None,
+                    last_attacker: None,
+                    badges: 0,
+                    latency: Latency::default(),
+                    connection: Some(msg.recipient.clone()),
+                    disconnected_tick: None,
+                    session_token: Uuid::new_v4(),
+                    player_token,
                     game_state: GameState::new(),
                 },
             );
 
+            // The first player to join a private lobby hosts it
+            if self.config.is_private && self.host.is_none() {
+                self.host = Some(msg.client_id);
+            }
+
             msg.recipient.do_send(ServerMessage {
                 server_message: LobbyJoinSuccess {
-                    player_id: msg.client_id.clone(),
+                    lobby_id: self.id.clone(),
+                    player_id: msg.client_id,
+                    session_token: self.players[&msg.client_id].session_token,
+                    player_token,
+                    config: self.config,
+                    host: self.host,
                     players: player_infos,
                 },
             });
@@ -185,14 +1077,81 @@ impl Handler<PlayerMessage> for LobbyActor {
                 player: self.players.get(&msg.client_id).unwrap().info.clone(),
             });
 
-            if self.players.len() == 2 {
-                self.do_game_start();
+            // Players joining mid-countdown need to catch up on it
+            if self.state == LobbyState::Countdown {
+                let player = &self.players[&msg.client_id];
+                player.send_message(LobbyStateChangeEvent {
+                    new_state: LobbyState::Countdown,
+                });
+                player.send_message(S2CMessage::CountdownEvent {
+                    seconds_remaining: self.countdown_remaining,
+                });
+            }
+
+            self.check_start_conditions();
+            return;
+        }
+
+        if let C2SMessage::Resume { session_token, .. } = msg.client_message {
+            self.do_resume(session_token, msg.recipient);
+            return;
+        }
+
+        if let C2SMessage::SpectateRequest { .. } = msg.client_message {
+            self.do_spectate(msg.client_id, msg.recipient);
+            return;
+        }
+
+        // Spectators simulate the games too, so they can fall out of sync just like players
+        match msg.client_message {
+            C2SMessage::DesyncReport {
+                tick,
+                player,
+                expected,
+                actual,
+            } => {
+                eprintln!(
+                    "Desync in lobby {}: {} has {:016x} for {} at tick {}, expected {:016x}",
+                    self.id, msg.client_id, actual, player, tick, expected
+                );
+                return;
+            }
+            C2SMessage::SnapshotRequest { players } => {
+                // Throttled requests get an empty snapshot, so the client can ask again later
+                let now = Instant::now();
+                let throttled = self
+                    .last_snapshots
+                    .get(&msg.client_id)
+                    .is_some_and(|last| now.duration_since(*last) < SNAPSHOT_INTERVAL);
+                let game_states = if throttled {
+                    vec![]
+                } else {
+                    self.last_snapshots.insert(msg.client_id, now);
+                    self.players
+                        .iter()
+                        .filter(|(uuid, _)| players.is_empty() || players.contains(uuid))
+                        .map(|(uuid, player)| (*uuid, player.game_state.clone()))
+                        .collect()
+                };
+                msg.recipient.do_send(ServerMessage {
+                    server_message: S2CMessage::SnapshotEvent { game_states },
+                });
+                return;
             }
+            _ => {}
+        }
+
+        if self.spectators.contains_key(&msg.client_id) {
+            msg.recipient.do_send(ServerMessage {
+                server_message: InvalidMessage {
+                    error: "Spectators cannot do that!".to_string(),
+                },
+            });
             return;
         }
 
         let player_opt = self.players.get_mut(&msg.client_id);
-        if let None = player_opt {
+        if player_opt.is_none() {
             msg.recipient.do_send(ServerMessage {
                 server_message: InvalidMessage {
                     error: "Not a player in the lobby!".to_string(),
@@ -210,15 +1169,100 @@ impl Handler<PlayerMessage> for LobbyActor {
                     Some((input_tick, _)) => *input_tick + 1,
                 };
 
-                if tick < expected_tick {
+                // Inputs for ticks that were already simulated are dropped, and the client
+                // rewinds its copy of the game to match
+                let accepted = player.info.state == PlayerState::Playing && tick >= expected_tick;
+                if accepted {
+                    player.future_inputs.push_back((tick, input));
+                }
+                player.send_message(S2CMessage::InputAckEvent { tick, accepted });
+            }
+            C2SMessage::Pong { id } => {
+                if player.latency.finish_ping(id) {
+                    let interval = self.scheduler.interval();
+                    player.send_message(S2CMessage::TimeSyncEvent {
+                        tick: self.current_tick.load(Ordering::Relaxed),
+                        input_delay: player.latency.input_delay(interval),
+                        rtt_ms: player.latency.rtt().unwrap_or_default().as_millis() as u64,
+                    });
+                }
+            }
+            C2SMessage::SetTargetingStrategy { strategy } => {
+                player.strategy = strategy;
+            }
+            C2SMessage::SetReady { is_ready } => {
+                if player.info.is_ready == is_ready {
                     return;
                 }
+                player.info.is_ready = is_ready;
+                self.broadcast(S2CMessage::PlayerReadyEvent {
+                    player: msg.client_id,
+                    is_ready,
+                });
+                self.check_start_conditions();
+            }
+            message @ (C2SMessage::StartGame
+            | C2SMessage::KickPlayer { .. }
+            | C2SMessage::ChangeLobbySettings { .. }
+            | C2SMessage::TransferHost { .. }) => {
+                let result = if self.host == Some(msg.client_id) {
+                    self.handle_host_message(message)
+                } else {
+                    Err("Only the host can do that!".to_string())
+                };
 
-                player.future_inputs.push_back((tick, input));
+                if let Err(error) = result {
+                    msg.recipient.do_send(ServerMessage {
+                        server_message: InvalidMessage { error },
+                    });
+                }
+            }
+            C2SMessage::QueueForMatch { .. } => {
+                msg.recipient.do_send(ServerMessage {
+                    server_message: InvalidMessage {
+                        error: "Already in a lobby!".to_string(),
+                    },
+                });
             }
-            C2SMessage::LobbyJoinRequest { .. } => {
+            C2SMessage::LobbyJoinRequest { .. }
+            | C2SMessage::Resume { .. }
+            | C2SMessage::SpectateRequest { .. }
+            | C2SMessage::DesyncReport { .. }
+            | C2SMessage::SnapshotRequest { .. } => {
                 unreachable!();
             }
         }
     }
 }
+
+impl Handler<PlayerDisconnected> for LobbyActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: PlayerDisconnected, _ctx: &mut Self::Context) -> Self::Result {
+        self.last_snapshots.remove(&msg.client_id);
+        if self.spectators.remove(&msg.client_id).is_some() {
+            return;
+        }
+
+        let player = match self.players.get_mut(&msg.client_id) {
+            Some(player) => player,
+            None => return,
+        };
+
+        // The player may already have resumed on a newer connection
+        if player.connection.as_ref() != Some(&msg.connection) {
+            return;
+        }
+        player.connection = None;
+
+        // Mid-game, living players get a grace period before forfeiting; otherwise they leave
+        if self.state == LobbyState::InPlay {
+            if player.info.state == PlayerState::Playing {
+                player.disconnected_tick = Some(self.current_tick.load(Ordering::Relaxed));
+            }
+            return;
+        }
+
+        self.remove_player(msg.client_id);
+    }
+}
@@ -1,19 +1,37 @@
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use actix::Addr;
+use actix::{Actor, Addr, Arbiter};
 use actix_web::middleware::Logger;
 use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use web::Data;
 
 use game::game::GameState;
 use game::input::Input;
+use game::messages::LobbyConfig;
 
+use crate::database::{Database, GetLeaderboard, GetRating, TopRatings};
 use crate::lobby::{LobbyActor, LobbyId};
+use crate::matchmaker::Matchmaker;
+use crate::ratings::RatingStore;
+use crate::scheduler::TickMetrics;
 use crate::server::game_websocket;
+use crate::storage::{
+    unix_time, GameStore, LeaderboardEntry, LeaderboardWindow, MemoryGameStore, SqliteGameStore,
+};
 
+mod database;
+mod latency;
 mod lobby;
+mod matchmaker;
+mod ratings;
+mod scheduler;
 mod server;
+mod storage;
 
 #[get("/")]
 async fn hello() -> impl Responder {
@@ -25,6 +43,96 @@ async fn echo(req_body: String) -> impl Responder {
     HttpResponse::Ok().body(req_body)
 }
 
+#[get("/api/ratings")]
+async fn top_ratings(database: Data<Addr<Database>>) -> impl Responder {
+    let limit = TOP_RATINGS_LIMIT;
+    match database.send(TopRatings { limit }).await {
+        Ok(Ok(ratings)) => HttpResponse::Ok().json(ratings),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+// Looked up by player token, as names are not unique
+#[get("/api/ratings/{player}")]
+async fn player_rating(player: web::Path<Uuid>, database: Data<Addr<Database>>) -> impl Responder {
+    let player = player.into_inner();
+    match database.send(GetRating { player }).await {
+        Ok(Ok(Some(rating))) => HttpResponse::Ok().json(rating),
+        Ok(Ok(None)) => HttpResponse::NotFound().finish(),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default)]
+    window: LeaderboardWindow,
+    #[serde(default)]
+    page: u32,
+    page_size: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct LeaderboardPage {
+    window: LeaderboardWindow,
+    page: u32,
+    page_size: u32,
+    entries: Vec<LeaderboardEntry>,
+}
+
+#[get("/api/leaderboard")]
+async fn leaderboard(
+    query: web::Query<LeaderboardQuery>,
+    database: Data<Addr<Database>>,
+) -> impl Responder {
+    let page_size = query
+        .page_size
+        .unwrap_or(DEFAULT_LEADERBOARD_PAGE_SIZE)
+        .clamp(1, MAX_LEADERBOARD_PAGE_SIZE);
+    let since = query.window.since(unix_time());
+
+    let entries = database
+        .send(GetLeaderboard {
+            since,
+            offset: query.page.saturating_mul(page_size),
+            limit: page_size,
+        })
+        .await;
+    match entries {
+        Ok(Ok(entries)) => HttpResponse::Ok().json(LeaderboardPage {
+            window: query.window,
+            page: query.page,
+            page_size,
+            entries,
+        }),
+        Ok(Err(e)) => HttpResponse::InternalServerError().body(e.to_string()),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/api/replays/{id}")]
+async fn replay(id: web::Path<Uuid>, data: Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    let path = data
+        .lock()
+        .unwrap()
+        .replay_dir
+        .join(format!("{}.replay", id));
+    match std::fs::read(path) {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HttpResponse::NotFound().finish(),
+        Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+#[get("/api/metrics")]
+async fn metrics(data: Data<Arc<Mutex<AppState>>>) -> impl Responder {
+    HttpResponse::Ok().json(data.lock().unwrap().tick_metrics)
+}
+
 async fn manual_hello() -> impl Responder {
     let mut body = GameState::new();
     body.tick(Input::Duck);
@@ -32,24 +140,74 @@ async fn manual_hello() -> impl Responder {
     HttpResponse::Ok().body(serde_json::to_string(&body).unwrap())
 }
 
+// Used when LOBBY_IDLE_TIMEOUT_SECS is not set
+const DEFAULT_LOBBY_IDLE_TIMEOUT_SECS: u64 = 60;
+// Used when DATABASE_PATH is not set
+const DEFAULT_DATABASE_PATH: &str = "dino99.db";
+// Used when REPLAY_DIR is not set
+const DEFAULT_REPLAY_DIR: &str = "replays";
+const TOP_RATINGS_LIMIT: u32 = 100;
+const DEFAULT_LEADERBOARD_PAGE_SIZE: u32 = 20;
+const MAX_LEADERBOARD_PAGE_SIZE: u32 = 100;
+
 pub(crate) struct AppState {
     lobbies: HashMap<LobbyId, Addr<LobbyActor>>,
+    // Lobbies that are empty, ended or otherwise idle for this long shut down
+    lobby_idle_timeout: Duration,
+    database: Addr<Database>,
+    replay_dir: PathBuf,
+    tick_metrics: TickMetrics,
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     std::env::set_var("RUST_LOG", "debug");
 
+    let lobby_idle_timeout = std::env::var("LOBBY_IDLE_TIMEOUT_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .unwrap_or(DEFAULT_LOBBY_IDLE_TIMEOUT_SECS);
+
+    let database_path =
+        std::env::var("DATABASE_PATH").unwrap_or_else(|_| DEFAULT_DATABASE_PATH.to_string());
+    let ratings = RatingStore::open(&database_path).expect("Failed to open the ratings database");
+    // GAME_STORE=memory keeps finished games in memory only, e.g. for local testing
+    let games: Box<dyn GameStore> = match std::env::var("GAME_STORE").as_deref() {
+        Ok("memory") => Box::<MemoryGameStore>::default(),
+        _ => Box::new(SqliteGameStore::open(&database_path).expect("Failed to open the database")),
+    };
+    let database_arbiter = Arbiter::new();
+    let database = Database::start_in_arbiter(&database_arbiter.handle(), move |_| {
+        Database::new(ratings, games)
+    });
+
     let data = Data::new(Arc::new(Mutex::new(AppState {
         lobbies: HashMap::new(),
+        lobby_idle_timeout: Duration::from_secs(lobby_idle_timeout),
+        database: database.clone(),
+        replay_dir: std::env::var("REPLAY_DIR")
+            .unwrap_or_else(|_| DEFAULT_REPLAY_DIR.to_string())
+            .into(),
+        tick_metrics: TickMetrics::default(),
     })));
 
+    let database = Data::new(database);
+    let matchmaker =
+        Data::new(Matchmaker::new(data.get_ref().clone(), LobbyConfig::default()).start());
+
     HttpServer::new(move || {
         App::new()
             .wrap(Logger::default())
             .app_data(data.clone())
+            .app_data(matchmaker.clone())
+            .app_data(database.clone())
             .service(hello)
             .service(echo)
+            .service(top_ratings)
+            .service(player_rating)
+            .service(leaderboard)
+            .service(replay)
+            .service(metrics)
             .route("/ws/", web::get().to(game_websocket))
             .route("/hey", web::get().to(manual_hello))
     })
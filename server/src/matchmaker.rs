@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use actix::prelude::*;
+use uuid::Uuid;
+
+use game::messages::{C2SMessage, LobbyConfig};
+
+use crate::lobby::{LobbyActor, LobbyId, PlayerMessage};
+use crate::server::ClientConnection;
+use crate::AppState;
+
+#[derive(Message)]
+#[rtype("()")]
+pub(crate) struct FindMatch {
+    pub(crate) client_id: Uuid,
+    pub(crate) name: String,
+    pub(crate) player_token: Option<Uuid>,
+    pub(crate) recipient: Addr<ClientConnection>,
+    // Lobbies the player was already sent to but could not join
+    pub(crate) excluded: Vec<LobbyId>,
+}
+
+// Sent to a connection before the join request is forwarded to its lobby
+#[derive(Message)]
+#[rtype("()")]
+pub(crate) struct MatchFound {
+    pub(crate) id: LobbyId,
+    pub(crate) lobby: Addr<LobbyActor>,
+}
+
+// Sent by public lobbies whenever their player count or state changes
+#[derive(Message)]
+#[rtype("()")]
+pub(crate) struct LobbyStatus {
+    pub(crate) id: LobbyId,
+    pub(crate) players: u32,
+    pub(crate) is_open: bool,
+}
+
+struct OpenLobby {
+    addr: Addr<LobbyActor>,
+    players: u32,
+    max_players: u32,
+}
+
+pub(crate) struct Matchmaker {
+    lobbies: Arc<Mutex<AppState>>,
+    config: LobbyConfig,
+    open_lobbies: HashMap<LobbyId, OpenLobby>,
+}
+
+impl Matchmaker {
+    pub(crate) fn new(lobbies: Arc<Mutex<AppState>>, config: LobbyConfig) -> Matchmaker {
+        Matchmaker {
+            lobbies,
+            config,
+            open_lobbies: HashMap::new(),
+        }
+    }
+
+    fn create_lobby(&mut self, ctx: &mut Context<Self>) -> LobbyId {
+        let id = format!("public-{}", Uuid::new_v4());
+
+        let app_state = &mut *self.lobbies.lock().unwrap();
+        let addr = LobbyActor::new(
+            id.clone(),
+            self.lobbies.clone(),
+            app_state.lobby_idle_timeout,
+            self.config,
+        )
+        .with_matchmaker(ctx.address())
+        .start();
+        app_state.lobbies.insert(id.clone(), addr.clone());
+
+        self.open_lobbies.insert(
+            id.clone(),
+            OpenLobby {
+                addr,
+                players: 0,
+                max_players: self.config.max_players,
+            },
+        );
+        id
+    }
+}
+
+impl Actor for Matchmaker {
+    type Context = Context<Self>;
+}
+
+impl Handler<FindMatch> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: FindMatch, ctx: &mut Self::Context) -> Self::Result {
+        // Fill up the fullest lobby first so that games start as soon as possible
+        let fullest = self
+            .open_lobbies
+            .iter()
+            .filter(|(id, lobby)| {
+                lobby.players < lobby.max_players
+                    && lobby.addr.connected()
+                    && !msg.excluded.contains(id)
+            })
+            .max_by_key(|(id, lobby)| (lobby.players, id.as_str()))
+            .map(|(id, _)| id.clone());
+
+        let id = match fullest {
+            Some(id) => id,
+            None => self.create_lobby(ctx),
+        };
+
+        // Counted right away, as the lobby only reports back once it has handled the join
+        let lobby = self.open_lobbies.get_mut(&id).unwrap();
+        lobby.players += 1;
+        let addr = lobby.addr.clone();
+        if lobby.players >= lobby.max_players {
+            self.open_lobbies.remove(&id);
+        }
+
+        msg.recipient.do_send(MatchFound {
+            id: id.clone(),
+            lobby: addr.clone(),
+        });
+        addr.do_send(PlayerMessage {
+            client_id: msg.client_id,
+            client_message: C2SMessage::LobbyJoinRequest {
+                name: msg.name,
+                lobby_id: id,
+                config: None,
+                player_token: msg.player_token,
+            },
+            recipient: msg.recipient,
+        });
+    }
+}
+
+impl Handler<LobbyStatus> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: LobbyStatus, _ctx: &mut Self::Context) -> Self::Result {
+        if !msg.is_open {
+            self.open_lobbies.remove(&msg.id);
+            return;
+        }
+
+        // Lobbies that filled up can open again when a player leaves before the game starts
+        if let Some(lobby) = self.open_lobbies.get_mut(&msg.id) {
+            lobby.players = msg.players;
+        } else if let Some(addr) = self.lobbies.lock().unwrap().lobbies.get(&msg.id) {
+            self.open_lobbies.insert(
+                msg.id,
+                OpenLobby {
+                    addr: addr.clone(),
+                    players: msg.players,
+                    max_players: self.config.max_players,
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use uuid::Uuid;
+
+const INITIAL_RATING: f64 = 1500.0;
+// Most a player's rating can change in a single game, split evenly between their opponents
+const K_FACTOR: f64 = 32.0;
+
+// Player tokens are secret, so ratings only ever show the name a player last played under
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Rating {
+    pub(crate) name: String,
+    pub(crate) rating: f64,
+    pub(crate) games: u32,
+}
+
+impl Rating {
+    fn new(name: &str) -> Rating {
+        Rating {
+            name: name.to_string(),
+            rating: INITIAL_RATING,
+            games: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Placement {
+    pub(crate) player: Uuid,
+    pub(crate) name: String,
+    // 1 being the winner
+    pub(crate) placement: u32,
+}
+
+// Ratings are keyed by player token, as names can be picked by anyone
+pub(crate) struct RatingStore {
+    connection: Connection,
+}
+
+impl RatingStore {
+    pub(crate) fn open(path: &str) -> rusqlite::Result<RatingStore> {
+        let connection = Connection::open(path)?;
+        connection.execute(
+            "CREATE TABLE IF NOT EXISTS ratings (
+                player TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                rating REAL NOT NULL,
+                games INTEGER NOT NULL
+            )",
+            [],
+        )?;
+        Ok(RatingStore { connection })
+    }
+
+    // Players that have not finished a game yet have no rating
+    pub(crate) fn get(&self, player: &Uuid) -> rusqlite::Result<Option<Rating>> {
+        get_rating(&self.connection, player)
+    }
+
+    pub(crate) fn top(&self, limit: u32) -> rusqlite::Result<Vec<Rating>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, rating, games FROM ratings ORDER BY rating DESC LIMIT ?1")?;
+        let ratings = statement.query_map([limit], |row| {
+            Ok(Rating {
+                name: row.get(0)?,
+                rating: row.get(1)?,
+                games: row.get(2)?,
+            })
+        })?;
+        ratings.collect()
+    }
+
+    // Updates ratings from the placements of a finished game
+    pub(crate) fn record_game(&mut self, placements: &[Placement]) -> rusqlite::Result<()> {
+        let transaction = self.connection.transaction()?;
+
+        let mut ratings = vec![];
+        for placement in placements {
+            let rating = get_rating(&transaction, &placement.player)?;
+            ratings.push(rating.unwrap_or_else(|| Rating::new(&placement.name)));
+        }
+
+        let deltas = rating_deltas(
+            &ratings
+                .iter()
+                .zip(placements)
+                .map(|(rating, placement)| (rating.rating, placement.placement))
+                .collect::<Vec<_>>(),
+        );
+
+        for ((rating, placement), delta) in ratings.iter().zip(placements).zip(deltas) {
+            transaction.execute(
+                "INSERT INTO ratings (player, name, rating, games) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT(player) DO UPDATE
+                 SET name = ?2, rating = rating + ?5, games = games + 1",
+                params![
+                    placement.player.to_string(),
+                    placement.name,
+                    rating.rating + delta,
+                    rating.games + 1,
+                    delta
+                ],
+            )?;
+        }
+
+        transaction.commit()
+    }
+}
+
+fn get_rating(connection: &Connection, player: &Uuid) -> rusqlite::Result<Option<Rating>> {
+    connection
+        .query_row(
+            "SELECT name, rating, games FROM ratings WHERE player = ?1",
+            [player.to_string()],
+            |row| {
+                Ok(Rating {
+                    name: row.get(0)?,
+                    rating: row.get(1)?,
+                    games: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+}
+
+// Multiplayer Elo: every game is scored as a set of head-to-head results between each pair of
+// players, where the better placement wins
+fn rating_deltas(players: &[(f64, u32)]) -> Vec<f64> {
+    if players.len() < 2 {
+        return vec![0.0; players.len()];
+    }
+
+    let k = K_FACTOR / (players.len() - 1) as f64;
+    players
+        .iter()
+        .enumerate()
+        .map(|(i, (rating, placement))| {
+            let total: f64 = players
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, (other_rating, other_placement))| {
+                    let actual = match placement.cmp(other_placement) {
+                        std::cmp::Ordering::Less => 1.0,
+                        std::cmp::Ordering::Equal => 0.5,
+                        std::cmp::Ordering::Greater => 0.0,
+                    };
+                    let expected = 1.0 / (1.0 + 10f64.powf((other_rating - rating) / 400.0));
+                    actual - expected
+                })
+                .sum();
+            k * total
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn placement(player: u128, name: &str, placement: u32) -> Placement {
+        Placement {
+            player: Uuid::from_u128(player),
+            name: name.to_string(),
+            placement,
+        }
+    }
+
+    #[test]
+    fn two_players_are_symmetric() {
+        let deltas = rating_deltas(&[(1500.0, 1), (1500.0, 2)]);
+        assert_eq!(deltas, vec![K_FACTOR / 2.0, -K_FACTOR / 2.0]);
+
+        // Beating a stronger player is worth more than beating a weaker one
+        let upset = rating_deltas(&[(1400.0, 1), (1600.0, 2)]);
+        let expected = rating_deltas(&[(1600.0, 1), (1400.0, 2)]);
+        assert!(upset[0] > expected[0]);
+        assert!((upset[0] + upset[1]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn deltas_sum_to_zero() {
+        let players = [
+            (1500.0, 3),
+            (1720.0, 1),
+            (1310.0, 2),
+            (1500.0, 4),
+            (1650.0, 2),
+        ];
+        let deltas = rating_deltas(&players);
+        assert_eq!(deltas.len(), players.len());
+        assert!(deltas.iter().sum::<f64>().abs() < 1e-9);
+        assert!(deltas.iter().all(|delta| delta.abs() <= K_FACTOR));
+    }
+
+    #[test]
+    fn ties() {
+        assert_eq!(rating_deltas(&[(1500.0, 1), (1500.0, 1)]), vec![0.0, 0.0]);
+
+        // The weaker player gains from a draw
+        let deltas = rating_deltas(&[(1400.0, 1), (1600.0, 1)]);
+        assert!(deltas[0] > 0.0 && deltas[1] < 0.0);
+        assert!((deltas[0] + deltas[1]).abs() < 1e-9);
+    }
+
+    #[test]
+    fn single_player_is_unrated() {
+        assert_eq!(rating_deltas(&[(1500.0, 1)]), vec![0.0]);
+        assert!(rating_deltas(&[]).is_empty());
+    }
+
+    #[test]
+    fn ratings_follow_the_token() {
+        let mut store = RatingStore::open(":memory:").unwrap();
+        assert!(store.get(&Uuid::from_u128(1)).unwrap().is_none());
+
+        // A second player going by the same name does not share the first one's rating
+        store
+            .record_game(&[placement(1, "alice", 1), placement(2, "bob", 2)])
+            .unwrap();
+        store
+            .record_game(&[placement(3, "alice", 2), placement(2, "bob", 1)])
+            .unwrap();
+
+        let first = store.get(&Uuid::from_u128(1)).unwrap().unwrap();
+        let impostor = store.get(&Uuid::from_u128(3)).unwrap().unwrap();
+        assert_eq!((first.name.as_str(), first.games), ("alice", 1));
+        assert!(first.rating > INITIAL_RATING);
+        assert_eq!(impostor.games, 1);
+        assert!(impostor.rating < INITIAL_RATING);
+
+        // Names follow whatever the player last played as
+        store
+            .record_game(&[placement(2, "robert", 1), placement(1, "alice", 2)])
+            .unwrap();
+        let renamed = store.get(&Uuid::from_u128(2)).unwrap().unwrap();
+        assert_eq!((renamed.name.as_str(), renamed.games), ("robert", 3));
+
+        let top = store.top(10).unwrap();
+        assert_eq!(top.len(), 3);
+        assert_eq!(top[0].name, "robert");
+    }
+}
@@ -0,0 +1,165 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+// Most ticks run at once to catch up after the lobby was delayed. Anything further behind is
+// skipped rather than letting a slow lobby fall further and further behind.
+const MAX_CATCH_UP_TICKS: u64 = 5;
+
+// Ticks at a fixed rate counted from a monotonic start instant, so that the time spent running
+// ticks and any scheduling delay do not add up over time
+pub(crate) struct TickScheduler {
+    start: Instant,
+    interval: Duration,
+    // Ticks run or skipped since `start`
+    ticks: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub(crate) struct DueTicks {
+    pub(crate) run: u64,
+    pub(crate) skipped: u64,
+}
+
+// Server wide counters of lobbies failing to keep up with their tick rate
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub(crate) struct TickMetrics {
+    // Ticks that took longer to run than the tick interval
+    pub(crate) overruns: u64,
+    // Extra ticks run to catch up after a delay
+    pub(crate) catch_up_ticks: u64,
+    pub(crate) skipped_ticks: u64,
+    pub(crate) slowest_overrun_micros: u64,
+}
+
+impl TickScheduler {
+    pub(crate) fn new(tick_rate: u32) -> TickScheduler {
+        TickScheduler {
+            start: Instant::now(),
+            interval: Duration::from_secs(1) / tick_rate,
+            ticks: 0,
+        }
+    }
+
+    pub(crate) fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    // Starts counting ticks from now, e.g. when a game starts
+    pub(crate) fn restart(&mut self, tick_rate: u32) {
+        *self = TickScheduler::new(tick_rate);
+    }
+
+    pub(crate) fn next_tick_at(&self) -> Instant {
+        self.start + self.interval * (self.ticks + 1) as u32
+    }
+
+    // Ticks that should have run by `now` but have not yet
+    pub(crate) fn due_ticks(&mut self, now: Instant) -> DueTicks {
+        let elapsed = now.saturating_duration_since(self.start);
+        let target = (elapsed.as_nanos() / self.interval.as_nanos()) as u64;
+        let behind = target.saturating_sub(self.ticks);
+        self.ticks += behind;
+
+        let run = behind.min(MAX_CATCH_UP_TICKS);
+        DueTicks {
+            run,
+            skipped: behind - run,
+        }
+    }
+}
+
+impl TickMetrics {
+    pub(crate) fn record(&mut self, due: DueTicks, overruns: u64, slowest_tick: Duration) {
+        self.overruns += overruns;
+        self.catch_up_ticks += due.run.saturating_sub(1);
+        self.skipped_ticks += due.skipped;
+        if overruns > 0 {
+            self.slowest_overrun_micros = self
+                .slowest_overrun_micros
+                .max(slowest_tick.as_micros() as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(scheduler: &TickScheduler, ticks: u32) -> Instant {
+        scheduler.start + scheduler.interval * ticks
+    }
+
+    #[test]
+    fn runs_ticks_as_they_come_due() {
+        let mut scheduler = TickScheduler::new(20);
+        assert_eq!(scheduler.interval(), Duration::from_millis(50));
+        assert_eq!(scheduler.due_ticks(scheduler.start).run, 0);
+
+        let due = scheduler.due_ticks(at(&scheduler, 1));
+        assert_eq!((due.run, due.skipped), (1, 0));
+        assert_eq!(scheduler.next_tick_at(), at(&scheduler, 2));
+
+        // Nothing new is due until the next interval has passed
+        let halfway = at(&scheduler, 1) + scheduler.interval / 2;
+        assert_eq!(scheduler.due_ticks(halfway).run, 0);
+
+        let due = scheduler.due_ticks(at(&scheduler, 3));
+        assert_eq!((due.run, due.skipped), (2, 0));
+    }
+
+    #[test]
+    fn caps_catch_up_ticks() {
+        let mut scheduler = TickScheduler::new(20);
+        scheduler.due_ticks(at(&scheduler, 2));
+
+        let due = scheduler.due_ticks(at(&scheduler, 20));
+        assert_eq!(due.run, MAX_CATCH_UP_TICKS);
+        assert_eq!(due.skipped, 18 - MAX_CATCH_UP_TICKS);
+
+        // Skipped ticks are not run later on
+        assert_eq!(scheduler.next_tick_at(), at(&scheduler, 21));
+        assert_eq!(scheduler.due_ticks(at(&scheduler, 20)).run, 0);
+        assert_eq!(scheduler.due_ticks(at(&scheduler, 21)).run, 1);
+    }
+
+    #[test]
+    fn restart_counts_from_now() {
+        let mut scheduler = TickScheduler::new(20);
+        scheduler.start -= scheduler.interval * 100;
+        assert_eq!(scheduler.due_ticks(Instant::now()).run, MAX_CATCH_UP_TICKS);
+
+        scheduler.restart(10);
+        assert_eq!(scheduler.interval(), Duration::from_millis(100));
+        assert_eq!(scheduler.next_tick_at(), at(&scheduler, 1));
+        assert_eq!(scheduler.due_ticks(scheduler.start).run, 0);
+        assert_eq!(scheduler.due_ticks(at(&scheduler, 1)).run, 1);
+    }
+
+    #[test]
+    fn records_metrics() {
+        let mut metrics = TickMetrics::default();
+        metrics.record(
+            DueTicks { run: 1, skipped: 0 },
+            0,
+            Duration::from_millis(80),
+        );
+        assert_eq!(metrics.catch_up_ticks, 0);
+        assert_eq!(metrics.slowest_overrun_micros, 0);
+
+        metrics.record(
+            DueTicks { run: 5, skipped: 3 },
+            2,
+            Duration::from_millis(70),
+        );
+        metrics.record(
+            DueTicks { run: 2, skipped: 0 },
+            1,
+            Duration::from_millis(60),
+        );
+        assert_eq!(metrics.overruns, 3);
+        assert_eq!(metrics.catch_up_ticks, 5);
+        assert_eq!(metrics.skipped_ticks, 3);
+        assert_eq!(metrics.slowest_overrun_micros, 70_000);
+    }
+}
@@ -2,26 +2,55 @@ use std::sync::{Arc, Mutex};
 
 use actix::prelude::*;
 use actix::{Actor, Addr, AsyncContext, StreamHandler};
+use actix_web::http::header;
 use actix_web::{web, Error, HttpRequest, HttpResponse};
 use actix_web_actors::ws;
 use uuid::Uuid;
 use web::{Data, Payload};
 
-use game::messages::C2SMessage;
-use game::messages::C2SMessage::LobbyJoinRequest;
-use game::messages::S2CMessage::InvalidMessage;
+use game::codec::{self, PlayerOrder, WireFormat};
+use game::messages::C2SMessage::{LobbyJoinRequest, QueueForMatch, Resume, SpectateRequest};
+use game::messages::S2CMessage::{
+    GameStartEvent, InvalidMessage, KickedEvent, LobbyClosedEvent, LobbyJoinFailureResponse,
+    LobbyJoinSuccess, ResumeSuccess, SpectateSuccess,
+};
+use game::messages::{C2SMessage, S2CMessage};
 
-use crate::lobby::{LobbyActor, PlayerMessage, ServerMessage};
+use crate::lobby::{LobbyActor, LobbyId, PlayerDisconnected, PlayerMessage, ServerMessage};
+use crate::matchmaker::{FindMatch, MatchFound, Matchmaker};
 use crate::AppState;
 
 pub(crate) struct ClientConnection {
     lobbies: Arc<Mutex<AppState>>,
+    matchmaker: Addr<Matchmaker>,
     lobby: Option<Addr<LobbyActor>>,
     id: Uuid,
+    // Picked when the connection is opened, only applies to messages sent to the client
+    format: WireFormat,
+    // Players of the lobby's current game, which binary tick events refer to by index
+    player_order: PlayerOrder,
+    // Set from queueing for a public lobby until the player has joined one
+    queued: Option<Queued>,
+}
+
+struct Queued {
+    name: String,
+    player_token: Option<Uuid>,
+    // Lobbies the matchmaker has sent the player to so far
+    tried: Vec<LobbyId>,
 }
 
 impl Actor for ClientConnection {
     type Context = ws::WebsocketContext<Self>;
+
+    fn stopped(&mut self, ctx: &mut Self::Context) {
+        if let Some(lobby) = &self.lobby {
+            lobby.do_send(PlayerDisconnected {
+                client_id: self.id,
+                connection: ctx.address(),
+            });
+        }
+    }
 }
 
 // Handle incoming websocket messages
@@ -30,48 +59,135 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ClientConnection
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                let parsed = serde_json::from_str::<C2SMessage>(&text);
-                match parsed {
-                    Ok(LobbyJoinRequest { lobby_id, name }) if self.lobby.is_none() => {
-                        let lobby_map = &mut self.lobbies.lock().unwrap().lobbies;
-                        self.lobby = Option::from(match lobby_map.get_mut(lobby_id.as_str()) {
-                            Some(found) => found.clone(),
-                            None => {
-                                let new_lobby = LobbyActor::new().start();
-                                lobby_map.insert(lobby_id.clone(), new_lobby.clone());
-                                dbg!("Making new lobby");
-                                new_lobby
-                            }
-                        });
-                        self.lobby.as_ref().unwrap().do_send(PlayerMessage {
-                            client_id: self.id,
-                            client_message: { LobbyJoinRequest { lobby_id, name } },
-                            recipient: ctx.address(),
-                        });
+                let parsed = serde_json::from_str::<C2SMessage>(&text).map_err(|e| e.to_string());
+                self.handle_client_message(parsed, ctx);
+            }
+            Ok(ws::Message::Binary(bin)) => {
+                let parsed = codec::decode_binary::<C2SMessage>(&bin).map_err(|e| e.to_string());
+                self.handle_client_message(parsed, ctx);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl ClientConnection {
+    fn send(&self, message: S2CMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match self.format {
+            WireFormat::Json => ctx.text(serde_json::to_string(&message).unwrap()),
+            WireFormat::Binary => ctx.binary(codec::encode_binary(&codec::compact(
+                message,
+                &self.player_order,
+            ))),
+        }
+    }
+
+    fn handle_client_message(
+        &mut self,
+        parsed: Result<C2SMessage, String>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        match parsed {
+            Ok(LobbyJoinRequest {
+                lobby_id,
+                name,
+                config,
+                player_token,
+            }) if self.lobby.is_none() => {
+                let app_state = &mut *self.lobbies.lock().unwrap();
+                let lobby_map = &mut app_state.lobbies;
+                self.lobby = Option::from(match lobby_map.get_mut(lobby_id.as_str()) {
+                    Some(found) if found.connected() => found.clone(),
+                    _ => {
+                        let lobby_config = config.unwrap_or_default();
+                        if let Err(reason) = lobby_config.validate() {
+                            self.send(LobbyJoinFailureResponse { reason }, ctx);
+                            return;
+                        }
+                        let new_lobby = LobbyActor::new(
+                            lobby_id.clone(),
+                            self.lobbies.clone(),
+                            app_state.lobby_idle_timeout,
+                            lobby_config,
+                        )
+                        .start();
+                        lobby_map.insert(lobby_id.clone(), new_lobby.clone());
+                        dbg!("Making new lobby");
+                        new_lobby
                     }
-                    Ok(message) if self.lobby.is_some() => {
-                        self.lobby.as_ref().unwrap().do_send(PlayerMessage {
+                });
+                self.lobby.as_ref().unwrap().do_send(PlayerMessage {
+                    client_id: self.id,
+                    client_message: LobbyJoinRequest {
+                        lobby_id,
+                        name,
+                        config,
+                        player_token,
+                    },
+                    recipient: ctx.address(),
+                });
+            }
+            // Already waiting for a match
+            Ok(QueueForMatch { .. }) if self.queued.is_some() => {}
+            Ok(QueueForMatch { name, player_token }) if self.lobby.is_none() => {
+                self.queued = Some(Queued {
+                    name: name.clone(),
+                    player_token,
+                    tried: vec![],
+                });
+                self.matchmaker.do_send(FindMatch {
+                    client_id: self.id,
+                    name,
+                    player_token,
+                    recipient: ctx.address(),
+                    excluded: vec![],
+                });
+            }
+            // Unlike joining, these never create a lobby
+            Ok(message @ (Resume { .. } | SpectateRequest { .. })) if self.lobby.is_none() => {
+                let lobby_id = match &message {
+                    Resume { lobby_id, .. } | SpectateRequest { lobby_id, .. } => lobby_id.clone(),
+                    _ => unreachable!(),
+                };
+                let lobby = self
+                    .lobbies
+                    .lock()
+                    .unwrap()
+                    .lobbies
+                    .get(lobby_id.as_str())
+                    .filter(|lobby| lobby.connected())
+                    .cloned();
+                match lobby {
+                    Some(lobby) => {
+                        lobby.do_send(PlayerMessage {
                             client_id: self.id,
                             client_message: message,
                             recipient: ctx.address(),
                         });
+                        self.lobby = Some(lobby);
                     }
-                    Err(error) => ctx.text(
-                        serde_json::to_string(&InvalidMessage {
-                            error: error.to_string(),
-                        })
-                        .unwrap(),
-                    ),
-                    _ => ctx.text(
-                        serde_json::to_string(&InvalidMessage {
-                            error: "No handlers".to_string(),
-                        })
-                        .unwrap(),
+                    None => self.send(
+                        LobbyJoinFailureResponse {
+                            reason: "Lobby not found".to_string(),
+                        },
+                        ctx,
                     ),
                 }
             }
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
-            _ => (),
+            Ok(message) if self.lobby.is_some() => {
+                self.lobby.as_ref().unwrap().do_send(PlayerMessage {
+                    client_id: self.id,
+                    client_message: message,
+                    recipient: ctx.address(),
+                });
+            }
+            Err(error) => self.send(InvalidMessage { error }, ctx),
+            _ => self.send(
+                InvalidMessage {
+                    error: "No handlers".to_string(),
+                },
+                ctx,
+            ),
         }
     }
 }
@@ -81,7 +197,50 @@ impl Handler<ServerMessage> for ClientConnection {
     type Result = ();
 
     fn handle(&mut self, msg: ServerMessage, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(serde_json::to_string(&msg.server_message).unwrap());
+        // A resumed connection takes over the identity of the player it was re-bound to
+        match &msg.server_message {
+            ResumeSuccess {
+                player_id,
+                player_order,
+                ..
+            } => {
+                self.id = *player_id;
+                self.player_order = PlayerOrder::new(player_order.clone());
+            }
+            GameStartEvent { player_order, .. } | SpectateSuccess { player_order, .. } => {
+                self.player_order = PlayerOrder::new(player_order.clone())
+            }
+            LobbyJoinSuccess { .. } => self.queued = None,
+            // A lobby picked by the matchmaker can fill up or start before the join reaches it,
+            // in which case the player is queued again for any other lobby
+            LobbyJoinFailureResponse { .. } if self.queued.is_some() => {
+                let queued = self.queued.as_ref().unwrap();
+                self.lobby = None;
+                self.matchmaker.do_send(FindMatch {
+                    client_id: self.id,
+                    name: queued.name.clone(),
+                    player_token: queued.player_token,
+                    recipient: ctx.address(),
+                    excluded: queued.tried.clone(),
+                });
+                return;
+            }
+            // Free the connection up to join another lobby
+            LobbyClosedEvent | KickedEvent | LobbyJoinFailureResponse { .. } => self.lobby = None,
+            _ => {}
+        }
+        self.send(msg.server_message, ctx);
+    }
+}
+
+impl Handler<MatchFound> for ClientConnection {
+    type Result = ();
+
+    fn handle(&mut self, msg: MatchFound, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(queued) = &mut self.queued {
+            queued.tried.push(msg.id);
+        }
+        self.lobby = Some(msg.lobby);
     }
 }
 
@@ -89,16 +248,35 @@ pub(crate) async fn game_websocket(
     req: HttpRequest,
     stream: Payload,
     data: Data<Arc<Mutex<AppState>>>,
+    matchmaker: Data<Addr<Matchmaker>>,
 ) -> Result<HttpResponse, Error> {
-    let resp = ws::start(
+    // Clients list the protocols they support, most preferred first
+    let format = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .find_map(|protocol| WireFormat::from_protocol(protocol.trim()))
+        })
+        .unwrap_or_default();
+
+    let resp = ws::WsResponseBuilder::new(
         ClientConnection {
             lobbies: data.get_ref().clone(),
+            matchmaker: matchmaker.get_ref().clone(),
             lobby: None,
             id: Uuid::new_v4(),
+            format,
+            player_order: PlayerOrder::default(),
+            queued: None,
         },
         &req,
         stream,
-    );
+    )
+    .protocols(&[format.protocol()])
+    .start();
     println!("{:?}", resp);
     resp
 }
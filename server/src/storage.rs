@@ -0,0 +1,309 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub(crate) struct StorageError(String);
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError(e.to_string())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct GameResult {
+    pub(crate) player: Uuid,
+    pub(crate) username: String,
+    pub(crate) placement: u32,
+    pub(crate) score: u64,
+    pub(crate) badges: u32,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct GameRecord {
+    pub(crate) lobby_id: String,
+    // Seconds since the unix epoch
+    pub(crate) finished_at: u64,
+    pub(crate) duration_ticks: u64,
+    pub(crate) results: Vec<GameResult>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct LeaderboardEntry {
+    pub(crate) username: String,
+    pub(crate) score: u64,
+    pub(crate) placement: u32,
+    pub(crate) lobby_id: String,
+    pub(crate) finished_at: u64,
+    pub(crate) duration_ticks: u64,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LeaderboardWindow {
+    Daily,
+    Weekly,
+    #[default]
+    AllTime,
+}
+
+impl LeaderboardWindow {
+    // Earliest finish time included in the window
+    pub(crate) fn since(&self, now: u64) -> u64 {
+        match self {
+            LeaderboardWindow::Daily => now.saturating_sub(24 * 60 * 60),
+            LeaderboardWindow::Weekly => now.saturating_sub(7 * 24 * 60 * 60),
+            LeaderboardWindow::AllTime => 0,
+        }
+    }
+}
+
+pub(crate) fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+// Record of finished games, backed by a database file or kept in memory
+pub(crate) trait GameStore: Send {
+    fn record_game(&mut self, game: &GameRecord) -> Result<(), StorageError>;
+
+    // Best individual results of games finished at or after `since`, highest score first
+    fn leaderboard(
+        &self,
+        since: u64,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<LeaderboardEntry>, StorageError>;
+}
+
+pub(crate) struct SqliteGameStore {
+    connection: Connection,
+}
+
+impl SqliteGameStore {
+    pub(crate) fn open(path: &str) -> Result<SqliteGameStore, StorageError> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS games (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                lobby_id TEXT NOT NULL,
+                finished_at INTEGER NOT NULL,
+                duration_ticks INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS results (
+                game_id INTEGER NOT NULL REFERENCES games(id),
+                player TEXT NOT NULL,
+                username TEXT NOT NULL,
+                placement INTEGER NOT NULL,
+                score INTEGER NOT NULL,
+                badges INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS games_finished_at ON games(finished_at);
+            CREATE INDEX IF NOT EXISTS results_score ON results(score);",
+        )?;
+        Ok(SqliteGameStore { connection })
+    }
+}
+
+impl GameStore for SqliteGameStore {
+    fn record_game(&mut self, game: &GameRecord) -> Result<(), StorageError> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO games (lobby_id, finished_at, duration_ticks) VALUES (?1, ?2, ?3)",
+            params![game.lobby_id, game.finished_at, game.duration_ticks],
+        )?;
+
+        let game_id = transaction.last_insert_rowid();
+        for result in &game.results {
+            transaction.execute(
+                "INSERT INTO results (game_id, player, username, placement, score, badges)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    game_id,
+                    result.player.to_string(),
+                    result.username,
+                    result.placement,
+                    result.score,
+                    result.badges
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn leaderboard(
+        &self,
+        since: u64,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<LeaderboardEntry>, StorageError> {
+        let mut statement = self.connection.prepare(
+            "SELECT results.username, results.score, results.placement, games.lobby_id,
+                    games.finished_at, games.duration_ticks
+             FROM results JOIN games ON games.id = results.game_id
+             WHERE games.finished_at >= ?1
+             ORDER BY results.score DESC, games.finished_at ASC
+             LIMIT ?2 OFFSET ?3",
+        )?;
+        let entries = statement.query_map(params![since, limit, offset], |row| {
+            Ok(LeaderboardEntry {
+                username: row.get(0)?,
+                score: row.get(1)?,
+                placement: row.get(2)?,
+                lobby_id: row.get(3)?,
+                finished_at: row.get(4)?,
+                duration_ticks: row.get(5)?,
+            })
+        })?;
+        Ok(entries.collect::<Result<_, _>>()?)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryGameStore {
+    games: Vec<GameRecord>,
+}
+
+impl GameStore for MemoryGameStore {
+    fn record_game(&mut self, game: &GameRecord) -> Result<(), StorageError> {
+        self.games.push(game.clone());
+        Ok(())
+    }
+
+    fn leaderboard(
+        &self,
+        since: u64,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<LeaderboardEntry>, StorageError> {
+        let mut entries: Vec<LeaderboardEntry> = self
+            .games
+            .iter()
+            .filter(|game| game.finished_at >= since)
+            .flat_map(|game| {
+                game.results.iter().map(|result| LeaderboardEntry {
+                    username: result.username.clone(),
+                    score: result.score,
+                    placement: result.placement,
+                    lobby_id: game.lobby_id.clone(),
+                    finished_at: game.finished_at,
+                    duration_ticks: game.duration_ticks,
+                })
+            })
+            .collect();
+        entries.sort_by_key(|entry| (std::cmp::Reverse(entry.score), entry.finished_at));
+
+        Ok(entries
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn game(lobby_id: &str, finished_at: u64, scores: &[(&str, u64)]) -> GameRecord {
+        GameRecord {
+            lobby_id: lobby_id.to_string(),
+            finished_at,
+            duration_ticks: 100,
+            results: scores
+                .iter()
+                .enumerate()
+                .map(|(i, (username, score))| GameResult {
+                    player: Uuid::new_v4(),
+                    username: username.to_string(),
+                    placement: i as u32 + 1,
+                    score: *score,
+                    badges: 0,
+                })
+                .collect(),
+        }
+    }
+
+    fn usernames(entries: &[LeaderboardEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| entry.username.as_str())
+            .collect()
+    }
+
+    fn check_leaderboard(mut store: impl GameStore) {
+        let now = 100 * DAY;
+        store
+            .record_game(&game(
+                "old",
+                now - 30 * DAY,
+                &[("ancient", 900), ("slow", 10)],
+            ))
+            .unwrap();
+        store
+            .record_game(&game("week", now - 3 * DAY, &[("weekly", 500)]))
+            .unwrap();
+        store
+            .record_game(&game("today", now - 60, &[("daily", 300), ("tied", 500)]))
+            .unwrap();
+
+        let all_time = store
+            .leaderboard(LeaderboardWindow::AllTime.since(now), 0, 10)
+            .unwrap();
+        assert_eq!(
+            usernames(&all_time),
+            ["ancient", "weekly", "tied", "daily", "slow"]
+        );
+        assert_eq!(all_time[0].lobby_id, "old");
+        assert_eq!(all_time[0].placement, 1);
+        assert_eq!(all_time[0].duration_ticks, 100);
+
+        let weekly = store
+            .leaderboard(LeaderboardWindow::Weekly.since(now), 0, 10)
+            .unwrap();
+        assert_eq!(usernames(&weekly), ["weekly", "tied", "daily"]);
+
+        let daily = store
+            .leaderboard(LeaderboardWindow::Daily.since(now), 0, 10)
+            .unwrap();
+        assert_eq!(usernames(&daily), ["tied", "daily"]);
+
+        let page = store
+            .leaderboard(LeaderboardWindow::AllTime.since(now), 1, 2)
+            .unwrap();
+        assert_eq!(usernames(&page), ["weekly", "tied"]);
+    }
+
+    #[test]
+    fn window_start() {
+        assert_eq!(LeaderboardWindow::Daily.since(2 * DAY), DAY);
+        assert_eq!(LeaderboardWindow::Weekly.since(10 * DAY), 3 * DAY);
+        assert_eq!(LeaderboardWindow::Weekly.since(DAY), 0);
+        assert_eq!(LeaderboardWindow::AllTime.since(10 * DAY), 0);
+    }
+
+    #[test]
+    fn memory_leaderboard() {
+        check_leaderboard(MemoryGameStore::default());
+    }
+
+    #[test]
+    fn sqlite_leaderboard() {
+        check_leaderboard(SqliteGameStore::open(":memory:").unwrap());
+    }
+}